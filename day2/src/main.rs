@@ -1,77 +1,159 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Error, Read};
+extern crate unicode_segmentation;
 
-fn analyze_id(id: &str) -> (bool, bool) {
-    let mut counts: HashMap<char, i64> = HashMap::new();
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use unicode_segmentation::UnicodeSegmentation;
 
-    for c in id.chars() {
-        let count = match counts.get(&c) {
-            Some(n) => *n,
-            None => 0,
-        };
+// What counts as a single "letter" when counting or comparing IDs.  The puzzle input
+// is plain ASCII so all three agree there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompareMode {
+    Bytes,
+    Chars,
+    Graphemes,
+}
 
-        counts.insert(c, count + 1);
+impl CompareMode {
+    // Every unit is a slice of the ID's UTF-8 encoding, so two units are equal iff
+    // their bytes are.
+    fn units(self, id: &str) -> Vec<&[u8]> {
+        match self {
+            CompareMode::Bytes => id.as_bytes().chunks(1).collect(),
+            CompareMode::Chars => {
+                id.char_indices().map(|(i, c)| &id.as_bytes()[i..i + c.len_utf8()]).collect()
+            }
+            CompareMode::Graphemes => id.graphemes(true).map(|g| g.as_bytes()).collect(),
+        }
     }
+}
 
-    let mut has_two = false;
-    let mut has_three = false;
+fn parse_mode(s: &str) -> Result<CompareMode, Error> {
+    match s {
+        "bytes" => Ok(CompareMode::Bytes),
+        "chars" => Ok(CompareMode::Chars),
+        "graphemes" => Ok(CompareMode::Graphemes),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unrecognized mode \"{}\"", s))),
+    }
+}
 
-    for (_, n) in &counts {
-        if *n == 2 {
-            has_two = true;
-        }
-        if *n == 3 {
-            has_three = true;
+// In `Bytes` mode a unit (or a run of them) can be part of a multi-byte character.
+// Those stray bytes are shown escaped rather than silently replaced.
+fn render_units(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for chunk in bytes.utf8_chunks() {
+        s.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            s.push_str(&format!("\\x{:02x}", b));
         }
     }
-
-    (has_two, has_three)
+    s
 }
 
-fn checksum(ids: &Vec<String>) -> i64 {
-    let mut twos = 0;
-    let mut threes = 0;
+// Returns, for each multiplicity k, the set of letters that appear exactly k times in `id`.
+fn letter_multiplicities(id: &str, mode: CompareMode) -> BTreeMap<usize, BTreeSet<String>> {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
 
-    for id in ids {
-        let res = analyze_id(&id);
-        if res.0 {
-            twos += 1;
-        }
-        if res.1 {
-            threes += 1;
-        }
+    for u in mode.units(id) {
+        *counts.entry(u).or_insert(0) += 1;
+    }
+
+    let mut profile: BTreeMap<usize, BTreeSet<String>> = BTreeMap::new();
+    for (u, n) in counts {
+        profile.entry(n).or_default().insert(render_units(u));
     }
 
-    twos * threes
+    profile
 }
 
-fn find_common(a: &str, b: &str) -> Option<String> {
-    if a.len() != b.len() {
+#[cfg(test)]
+fn analyze_id(id: &str, mode: CompareMode) -> (bool, bool) {
+    let profile = letter_multiplicities(id, mode);
+    (profile.contains_key(&2), profile.contains_key(&3))
+}
+
+// The puzzle's checksum is the `{2, 3}` case of this: for every k in `ks`, count the IDs
+// that have some letter appearing exactly k times, then multiply those counts together.
+fn checksum_for(ids: &[String], ks: &[usize], mode: CompareMode) -> i64 {
+    let profiles: Vec<_> = ids.iter().map(|id| letter_multiplicities(id, mode)).collect();
+
+    ks.iter().map(|k| profiles.iter().filter(|p| p.contains_key(k)).count() as i64).product()
+}
+
+fn checksum(ids: &[String], mode: CompareMode) -> i64 {
+    checksum_for(ids, &[2, 3], mode)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Difference {
+    pos: usize,
+    a: String,
+    b: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct IdDiff {
+    diffs: Vec<Difference>,
+    common: String,
+}
+
+// Compares two IDs position by position, giving up as soon as more than `max_diffs`
+// positions differ.  IDs of different lengths are never comparable.
+fn diff_ids(a: &str, b: &str, max_diffs: usize, mode: CompareMode) -> Option<IdDiff> {
+    let (ua, ub) = (mode.units(a), mode.units(b));
+    if ua.len() != ub.len() {
         return None;
     }
 
-    let mut deviations = 0;
-    let mut common = String::new();
-    for i in a.chars().zip(b.chars()) {
-        if i.0 == i.1 {
-            common.push(i.0);
+    let mut diffs = Vec::new();
+    let mut common = Vec::new();
+    for (pos, (ca, cb)) in ua.into_iter().zip(ub).enumerate() {
+        if ca == cb {
+            common.extend_from_slice(ca);
         } else {
-            deviations += 1;
-            if deviations > 1 {
+            if diffs.len() == max_diffs {
                 return None;
             }
+            diffs.push(Difference { pos, a: render_units(ca), b: render_units(cb) });
         }
     }
 
-    if deviations == 1 {
-        Some(common)
-    } else {
-        None
+    Some(IdDiff { diffs, common: render_units(&common) })
+}
+
+fn find_common(a: &str, b: &str, mode: CompareMode) -> Option<String> {
+    match diff_ids(a, b, 1, mode) {
+        Some(ref d) if d.diffs.len() == 1 => Some(d.common.clone()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct IdPair {
+    a: usize,
+    b: usize,
+    diff: IdDiff,
+}
+
+// Returns every pair of IDs (by index into `ids`) that are at most `k` positions apart.
+// Identical IDs are distance 0 and are reported as well.
+fn find_pairs_within(ids: &[String], k: usize, mode: CompareMode) -> Vec<IdPair> {
+    let mut pairs = Vec::new();
+    for i in 0..ids.len() {
+        for j in (i + 1)..ids.len() {
+            if let Some(diff) = diff_ids(&ids[i], &ids[j], k, mode) {
+                pairs.push(IdPair { a: i, b: j, diff });
+            }
+        }
     }
+    pairs
 }
 
-fn find_common_in_list(ids: &Vec<String>) -> Option<String> {
+// Kept around as the reference implementation for `find_common_in_list`.
+#[cfg(test)]
+fn find_common_in_list_pairwise(ids: &[String], mode: CompareMode) -> Option<String> {
     // We're assuming there are only two matching box IDs as inferred
     // by the question "What letters are common between the two correct
     // box IDs?"
@@ -81,7 +163,7 @@ fn find_common_in_list(ids: &Vec<String>) -> Option<String> {
     // stay simple.
     for i in 0..(ids.len() - 1) {
         for j in (i + 1)..ids.len() {
-            if let Some(s) = find_common(&ids[i], &ids[j]) {
+            if let Some(s) = find_common(&ids[i], &ids[j], mode) {
                 return Some(s);
             }
         }
@@ -90,6 +172,155 @@ fn find_common_in_list(ids: &Vec<String>) -> Option<String> {
     None
 }
 
+const HASH_BASE: u64 = 0x100_0000_01b3;
+
+// Two IDs of the same length differ only at position `p` iff their prefixes before `p` and
+// suffixes after `p` match.  Rolling hashes let us compute that "masked" key for every
+// position of an ID in O(L), so the whole list is bucketed in O(n * L).
+fn masked_keys(id: &str, mode: CompareMode) -> Vec<(usize, usize, u64, u64)> {
+    // Graphemes can be several bytes long, so each unit is first folded to a single value.
+    let units: Vec<u64> = mode
+        .units(id)
+        .iter()
+        .map(|u| u.iter().fold(0u64, |h, b| h.wrapping_mul(HASH_BASE).wrapping_add(*b as u64)))
+        .collect();
+    let n = units.len();
+
+    let mut prefix = vec![0u64; n + 1];
+    for i in 0..n {
+        prefix[i + 1] = prefix[i].wrapping_mul(HASH_BASE).wrapping_add(units[i]);
+    }
+
+    let mut suffix = vec![0u64; n + 1];
+    for i in (0..n).rev() {
+        suffix[i] = suffix[i + 1].wrapping_mul(HASH_BASE).wrapping_add(units[i]);
+    }
+
+    (0..n).map(|p| (n, p, prefix[p], suffix[p + 1])).collect()
+}
+
+// The index of the first copy of each distinct ID, in ascending order.
+fn distinct_ids(ids: &[String]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    (0..ids.len()).filter(|&i| seen.insert(&ids[i])).collect()
+}
+
+// Groups the given ID indices by masked key, alongside the masked position.  Indices
+// within a bucket keep the order they were given in.
+fn masked_buckets(
+    ids: &[String],
+    indices: &[usize],
+    mode: CompareMode,
+) -> Vec<(usize, Vec<usize>)> {
+    let mut buckets: HashMap<(usize, usize, u64, u64), Vec<usize>> = HashMap::new();
+    for &i in indices {
+        for key in masked_keys(&ids[i], mode) {
+            buckets.entry(key).or_default().push(i);
+        }
+    }
+    buckets.into_iter().filter(|(_, m)| m.len() > 1).map(|(key, m)| (key.1, m)).collect()
+}
+
+fn find_common_in_list(ids: &[String], mode: CompareMode) -> Option<String> {
+    // Identical IDs would share every bucket without ever matching, so only the first
+    // copy of each takes part.  A later copy can't give a lower pair than its first copy.
+    // Matches are verified with `find_common` so that hash collisions are never reported.
+    // Picking the lowest (i, j) pair keeps the answer identical to the pairwise search.
+    let mut best: Option<(usize, usize)> = None;
+    for (_, members) in masked_buckets(ids, &distinct_ids(ids), mode) {
+        'bucket: for (k, &i) in members.iter().enumerate() {
+            if let Some((best_i, _)) = best {
+                if best_i < i {
+                    break;
+                }
+            }
+            for &j in &members[k + 1..] {
+                if find_common(&ids[i], &ids[j], mode).is_some() {
+                    best = Some(best.map_or((i, j), |b| cmp::min(b, (i, j))));
+                    break 'bucket;
+                }
+            }
+        }
+    }
+
+    best.and_then(|(i, j)| find_common(&ids[i], &ids[j], mode))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Cluster {
+    members: Vec<usize>,
+    // Positions at which every member has the same letter, and those letters.
+    shared: Vec<usize>,
+    common: String,
+}
+
+fn find_root(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+
+    let mut i = i;
+    while parent[i] != root {
+        let next = parent[i];
+        parent[i] = root;
+        i = next;
+    }
+
+    root
+}
+
+// Groups IDs that are linked, directly or through other IDs, by single letter differences.
+// Only clusters with at least two members are returned, ordered by their first member.
+fn cluster_ids(ids: &[String], mode: CompareMode) -> Vec<Cluster> {
+    let mut parent: Vec<usize> = (0..ids.len()).collect();
+
+    // Only the first copy of each ID is bucketed.  A bucket can hold hash collisions, so
+    // its members are split up by what's actually left once the masked position is taken
+    // out.  Distinct IDs that agree on that are one letter apart, and linking each to the
+    // first of its group connects them all.
+    let distinct = distinct_ids(ids);
+    for (pos, members) in masked_buckets(ids, &distinct, mode) {
+        let mut groups: HashMap<Vec<&[u8]>, usize> = HashMap::new();
+        for j in members {
+            let mut units = mode.units(&ids[j]);
+            units.remove(pos);
+            let anchor = *groups.entry(units).or_insert(j);
+            let (a, b) = (find_root(&mut parent, anchor), find_root(&mut parent, j));
+            parent[b] = a;
+        }
+    }
+
+    // Copies join whatever cluster their first copy ended up in.
+    let first: HashMap<&String, usize> = distinct.iter().map(|&i| (&ids[i], i)).collect();
+    for (i, id) in ids.iter().enumerate() {
+        let (a, b) = (find_root(&mut parent, first[id]), find_root(&mut parent, i));
+        parent[b] = a;
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..ids.len() {
+        let root = find_root(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut clusters: Vec<Cluster> = groups
+        .into_values()
+        .filter(|members| members.iter().any(|&i| ids[i] != ids[members[0]]))
+        .map(|members| {
+            let units: Vec<Vec<&[u8]>> = members.iter().map(|&i| mode.units(&ids[i])).collect();
+            let shared: Vec<usize> = (0..units[0].len())
+                .filter(|&p| units.iter().all(|u| u[p] == units[0][p]))
+                .collect();
+            let common: Vec<u8> = shared.iter().flat_map(|&p| units[0][p].to_vec()).collect();
+            Cluster { members, shared, common: render_units(&common) }
+        })
+        .collect();
+    clusters.sort_by_key(|c| c.members[0]);
+
+    clusters
+}
+
 fn read<R: Read>(io: R) -> Result<Vec<String>, Error> {
     let br = BufReader::new(io);
     br.lines().collect()
@@ -97,8 +328,50 @@ fn read<R: Read>(io: R) -> Result<Vec<String>, Error> {
 
 fn main() -> Result<(), Error> {
     let lines = read(File::open("input.txt")?)?;
-    println!("Pt 1 answer: {}", checksum(&lines));
-    println!("Pt 2 answer: {}", find_common_in_list(&lines).unwrap());
+
+    // `--mode=<bytes|chars|graphemes>` picks what counts as a letter.  Defaults to chars.
+    let mut mode = CompareMode::Chars;
+    let mut args: Vec<String> = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--mode=") {
+            Some(m) => mode = parse_mode(m)?,
+            None => args.push(arg),
+        }
+    }
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["--within", k] => {
+            let k = k.parse().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+            for pair in find_pairs_within(&lines, k, mode) {
+                println!(
+                    "lines {} and {}: {} apart, common \"{}\"",
+                    pair.a + 1,
+                    pair.b + 1,
+                    pair.diff.diffs.len(),
+                    pair.diff.common
+                );
+            }
+            return Ok(());
+        }
+        ["--clusters"] => {
+            for cluster in cluster_ids(&lines, mode) {
+                let members: Vec<String> =
+                    cluster.members.iter().map(|i| (i + 1).to_string()).collect();
+                println!("lines {}: common \"{}\"", members.join(", "), cluster.common);
+            }
+            return Ok(());
+        }
+        [] => (),
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unrecognized option \"{}\"", other.join(" ")),
+            ))
+        }
+    }
+
+    println!("Pt 1 answer: {}", checksum(&lines, mode));
+    println!("Pt 2 answer: {}", find_common_in_list(&lines, mode).unwrap());
 
     Ok(())
 }
@@ -107,34 +380,252 @@ fn main() -> Result<(), Error> {
 mod test {
     use super::*;
 
+    const CHARS: CompareMode = CompareMode::Chars;
+
     #[test]
     fn part1_example_test() {
-        assert_eq!((false, false), analyze_id("abcdef"));
-        assert_eq!((true, true), analyze_id("bababc"));
-        assert_eq!((true, false), analyze_id("abbcde"));
-        assert_eq!((false, true), analyze_id("abcccd"));
-        assert_eq!((true, false), analyze_id("aabcdd"));
-        assert_eq!((true, false), analyze_id("abcdee"));
-        assert_eq!((false, true), analyze_id("ababab"));
-
-        let strings = ["abcdef", "bababc", "abbcde", "abcccd", "aabcdd", "abcdee", "ababab"]
+        assert_eq!((false, false), analyze_id("abcdef", CHARS));
+        assert_eq!((true, true), analyze_id("bababc", CHARS));
+        assert_eq!((true, false), analyze_id("abbcde", CHARS));
+        assert_eq!((false, true), analyze_id("abcccd", CHARS));
+        assert_eq!((true, false), analyze_id("aabcdd", CHARS));
+        assert_eq!((true, false), analyze_id("abcdee", CHARS));
+        assert_eq!((false, true), analyze_id("ababab", CHARS));
+
+        let strings: Vec<String> =
+            ["abcdef", "bababc", "abbcde", "abcccd", "aabcdd", "abcdee", "ababab"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+        assert_eq!(12, checksum(&strings, CHARS));
+    }
+
+    #[test]
+    fn letter_multiplicities_test() {
+        let profile = letter_multiplicities("bababc", CHARS);
+        assert_eq!(vec![1, 2, 3], profile.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["c"], profile[&1].iter().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["a"], profile[&2].iter().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["b"], profile[&3].iter().cloned().collect::<Vec<_>>());
+
+        assert!(letter_multiplicities("", CHARS).is_empty());
+    }
+
+    #[test]
+    fn checksum_for_test() {
+        let strings: Vec<String> =
+            ["abcdef", "bababc", "abbcde", "abcccd", "aabcdd", "abcdee", "ababab", "aaaabb"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+        assert_eq!(checksum(&strings, CHARS), checksum_for(&strings, &[2, 3], CHARS));
+        assert_eq!(15, checksum_for(&strings, &[2, 3, 4], CHARS));
+        assert_eq!(0, checksum_for(&strings, &[5], CHARS));
+        assert_eq!(1, checksum_for(&strings, &[], CHARS));
+    }
+
+    #[test]
+    fn part2_example_test() {
+        assert_eq!(None, find_common("a", "ab", CHARS));
+        assert_eq!(None, find_common("abcde", "axcye", CHARS));
+        assert_eq!(Some("fgij".to_string()), find_common("fghij", "fguij", CHARS));
+
+        let strings: Vec<String> = ["abcde", "fghij", "klmno", "pqrst", "fguij", "axcye", "wvxyz"]
             .iter()
             .map(|s| s.to_string())
             .collect();
-        assert_eq!(12, checksum(&strings));
+
+        assert_eq!(Some("fgij".to_string()), find_common_in_list(&strings, CHARS));
+    }
+
+    // Deterministic pseudo-random IDs so the hashed search can be checked against the
+    // pairwise one without pulling in a RNG crate.
+    fn gen_ids(n: usize, len: usize, alphabet: &[u8], seed: u64) -> Vec<String> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize
+        };
+
+        (0..n)
+            .map(|_| {
+                let l = len + next() % 2;
+                (0..l).map(|_| alphabet[next() % alphabet.len()] as char).collect()
+            })
+            .collect()
     }
 
     #[test]
-    fn part2_example_test() {
-        assert_eq!(None, find_common("a", "ab"));
-        assert_eq!(None, find_common("abcde", "axcye"));
-        assert_eq!(Some("fgij".to_string()), find_common("fghij", "fguij"));
+    fn find_common_in_list_differential_test() {
+        for seed in 0..50 {
+            let ids = gen_ids(40, 4, b"abc", seed);
+            assert_eq!(find_common_in_list_pairwise(&ids, CHARS), find_common_in_list(&ids, CHARS));
+        }
+    }
 
-        let strings = ["abcde", "fghij", "klmno", "pqrst", "fguij", "axcye", "wvxyz"]
+    #[test]
+    fn find_common_in_list_lengths_test() {
+        let strings: Vec<String> =
+            ["abcd", "abc", "abd", "abcd", "xbcde"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(Some("ab".to_string()), find_common_in_list(&strings, CHARS));
+
+        let strings: Vec<String> = ["abc", "abcd", "abc"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(None, find_common_in_list(&strings, CHARS));
+        assert_eq!(None, find_common_in_list(&[], CHARS));
+    }
+
+    #[test]
+    fn find_common_in_list_duplicates_test() {
+        let mut ids: Vec<String> = vec!["abcdefghijklmnopqrstuvwxyz".to_string(); 20000];
+        ids.push("zyxwvutsrqponmlkjihgfedcba".to_string());
+        assert_eq!(None, find_common_in_list(&ids, CHARS));
+
+        ids.insert(5000, "abcdefghijklmnopqrstuvwxyq".to_string());
+        ids.extend(vec!["abcdefghijklmnopqrstuvwxyq".to_string(); 20000]);
+        assert_eq!(Some("abcdefghijklmnopqrstuvwxy".to_string()), find_common_in_list(&ids, CHARS));
+
+        let ids = gen_ids(200, 3, b"ab", 7);
+        assert_eq!(find_common_in_list_pairwise(&ids, CHARS), find_common_in_list(&ids, CHARS));
+    }
+
+    #[test]
+    fn diff_ids_test() {
+        assert_eq!(None, diff_ids("abc", "abcd", 5, CHARS));
+        assert_eq!(None, diff_ids("abcde", "axcye", 1, CHARS));
+        assert_eq!(
+            Some(IdDiff {
+                diffs: vec![
+                    Difference { pos: 1, a: "b".to_string(), b: "x".to_string() },
+                    Difference { pos: 3, a: "d".to_string(), b: "y".to_string() }
+                ],
+                common: "ace".to_string()
+            }),
+            diff_ids("abcde", "axcye", 2, CHARS)
+        );
+        assert_eq!(
+            Some(IdDiff { diffs: vec![], common: "abc".to_string() }),
+            diff_ids("abc", "abc", 0, CHARS)
+        );
+    }
+
+    #[test]
+    fn find_pairs_within_test() {
+        let strings: Vec<String> = ["abcde", "fghij", "klmno", "pqrst", "fguij", "axcye", "wvxyz"]
             .iter()
             .map(|s| s.to_string())
             .collect();
 
-        assert_eq!(Some("fgij".to_string()), find_common_in_list(&strings));
+        let pairs = find_pairs_within(&strings, 1, CHARS);
+        assert_eq!(1, pairs.len());
+        assert_eq!((1, 4), (pairs[0].a, pairs[0].b));
+        assert_eq!(
+            vec![Difference { pos: 2, a: "h".to_string(), b: "u".to_string() }],
+            pairs[0].diff.diffs
+        );
+        assert_eq!("fgij", pairs[0].diff.common);
+
+        let pairs = find_pairs_within(&strings, 2, CHARS);
+        let found: Vec<_> = pairs.iter().map(|p| (p.a, p.b)).collect();
+        assert_eq!(vec![(0, 5), (1, 4)], found);
+        assert_eq!("ace", pairs[0].diff.common);
+
+        assert_eq!(
+            strings.len() * (strings.len() - 1) / 2,
+            find_pairs_within(&strings, 5, CHARS).len()
+        );
+        assert!(find_pairs_within(&strings, 0, CHARS).is_empty());
+    }
+
+    #[test]
+    fn compare_mode_units_test() {
+        // "é" spelled as 'e' followed by a combining acute accent.
+        let id = "cafe\u{301}";
+        assert_eq!(6, CompareMode::Bytes.units(id).len());
+        assert_eq!(5, CompareMode::Chars.units(id).len());
+        assert_eq!(4, CompareMode::Graphemes.units(id).len());
+        assert_eq!(b"e\xcc\x81", CompareMode::Graphemes.units(id)[3]);
+    }
+
+    #[test]
+    fn non_ascii_find_common_test() {
+        // Same number of bytes, different number of characters.
+        assert_eq!(None, find_common("é", "ab", CHARS));
+        // Different number of bytes, one character apart.
+        assert_eq!(Some("n".to_string()), find_common("né", "nx", CHARS));
+
+        assert_eq!(Some("caf".to_string()), find_common("café", "cafè", CHARS));
+        assert_eq!(Some("caf\\xc3".to_string()), find_common("café", "cafè", CompareMode::Bytes));
+        assert_eq!(
+            vec![Difference { pos: 4, a: "\\xa9".to_string(), b: "\\xa8".to_string() }],
+            diff_ids("café", "cafè", 1, CompareMode::Bytes).unwrap().diffs
+        );
+
+        // Precomposed vs. combining accents only line up when comparing graphemes.
+        assert_eq!(None, find_common("cafe\u{301}", "cafè", CHARS));
+        assert_eq!(
+            Some("caf".to_string()),
+            find_common("cafe\u{301}", "cafè", CompareMode::Graphemes)
+        );
+        assert_eq!(
+            Some("cafe\u{301}".to_string()),
+            find_common("cafe\u{301}s", "cafe\u{301}x", CompareMode::Graphemes)
+        );
+
+        let strings: Vec<String> =
+            ["zzzz", "cafe\u{301}", "cafè"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(None, find_common_in_list(&strings, CHARS));
+        assert_eq!(Some("caf".to_string()), find_common_in_list(&strings, CompareMode::Graphemes));
+    }
+
+    #[test]
+    fn non_ascii_multiplicities_test() {
+        let id = "e\u{301}e\u{301}ée";
+
+        let profile = letter_multiplicities(id, CHARS);
+        assert_eq!(vec!["é"], profile[&1].iter().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["\u{301}"], profile[&2].iter().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["e"], profile[&3].iter().cloned().collect::<Vec<_>>());
+
+        let profile = letter_multiplicities(id, CompareMode::Graphemes);
+        assert_eq!(vec!["e", "é"], profile[&1].iter().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["e\u{301}"], profile[&2].iter().cloned().collect::<Vec<_>>());
+        assert_eq!((true, false), analyze_id(id, CompareMode::Graphemes));
+        assert_eq!((true, true), analyze_id(id, CHARS));
+
+        assert_eq!(CompareMode::Graphemes, parse_mode("graphemes").unwrap());
+        assert!(parse_mode("words").is_err());
+    }
+
+    #[test]
+    fn cluster_ids_test() {
+        let strings: Vec<String> =
+            ["abcd", "xyzw", "abce", "zzzz", "abfe", "xyzq", "abcd", "qqqqq", "gbfe", "abc"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+
+        assert_eq!(
+            vec![
+                Cluster { members: vec![0, 2, 4, 6, 8], shared: vec![1], common: "b".to_string() },
+                Cluster { members: vec![1, 5], shared: vec![0, 1, 2], common: "xyz".to_string() },
+            ],
+            cluster_ids(&strings, CHARS)
+        );
+
+        let strings: Vec<String> = ["abc", "abc", "xyz"].iter().map(|s| s.to_string()).collect();
+        assert!(cluster_ids(&strings, CHARS).is_empty());
+
+        // The second copy of "aby" joins its cluster, while "cdx" stays out of it.
+        let strings: Vec<String> =
+            ["abx", "aby", "cdx", "abz", "aby"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            vec![Cluster {
+                members: vec![0, 1, 3, 4],
+                shared: vec![0, 1],
+                common: "ab".to_string()
+            }],
+            cluster_ids(&strings, CHARS)
+        );
+        assert!(cluster_ids(&[], CHARS).is_empty());
     }
 }
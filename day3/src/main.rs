@@ -1,10 +1,20 @@
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 #[macro_use]
 extern crate text_io;
 
 use std::cmp;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+// "The whole piece of fabric they're working on is a very large square - at least 1000
+// inches on each side."
+const FABRIC_SIZE: i64 = 1000;
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct Point {
@@ -12,7 +22,7 @@ struct Point {
     y: i64,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 struct Rectangle {
     x: i64,
     y: i64,
@@ -22,69 +32,864 @@ struct Rectangle {
 
 impl Rectangle {
     pub fn new(x: i64, y: i64, w: i64, h: i64) -> Rectangle {
-        Rectangle { x: x, y: y, x1: x + w, y1: y + h }
+        Rectangle { x, y, x1: x + w, y1: y + h }
     }
 
-    // It turns out this was unnecessary.  That'll teach me to read and think more, eh?
-    // Leaving it here incase it's useful later.
-    pub fn intersect(&self, other: &Rectangle) -> Option<Rectangle> {
-        if self.x >= other.x1 || self.y >= other.y1 || other.x >= self.x1 || other.y >= self.y1 {
-            return None;
-        }
+    // Rectangles are half open: they cover [x, x1) by [y, y1).  A negative width or
+    // height is treated the same as zero.
+    pub fn width(&self) -> i64 {
+        cmp::max(0, self.x1 - self.x)
+    }
 
-        Some(Rectangle {
+    pub fn height(&self) -> i64 {
+        cmp::max(0, self.y1 - self.y)
+    }
+
+    pub fn area(&self) -> i64 {
+        self.width() * self.height()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.area() == 0
+    }
+
+    pub fn contains(&self, pt: &Point) -> bool {
+        self.x <= pt.x && pt.x < self.x1 && self.y <= pt.y && pt.y < self.y1
+    }
+
+    // Returns the overlapping part of the two rectangles, or None if they don't share
+    // any area.
+    pub fn intersect(&self, other: &Rectangle) -> Option<Rectangle> {
+        let r = Rectangle {
             x: cmp::max(self.x, other.x),
             y: cmp::max(self.y, other.y),
-            x1: cmp::min(self.y1, other.y1),
+            x1: cmp::min(self.x1, other.x1),
             y1: cmp::min(self.y1, other.y1),
-        })
+        };
+
+        if r.is_empty() {
+            None
+        } else {
+            Some(r)
+        }
+    }
+
+    // Returns the smallest rectangle covering both.  Empty rectangles cover nothing, so
+    // they don't stretch the result.
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+
+        Rectangle {
+            x: cmp::min(self.x, other.x),
+            y: cmp::min(self.y, other.y),
+            x1: cmp::max(self.x1, other.x1),
+            y1: cmp::max(self.y1, other.y1),
+        }
+    }
+
+    // Returns up to four disjoint rectangles covering the parts of `self` outside of
+    // `other`: full width bands above and below the overlap, then the pieces to its left
+    // and right.
+    pub fn subtract(&self, other: &Rectangle) -> Vec<Rectangle> {
+        let overlap = match self.intersect(other) {
+            Some(r) => r,
+            None => {
+                return if self.is_empty() { Vec::new() } else { vec![self.clone()] };
+            }
+        };
+
+        let pieces = vec![
+            Rectangle { x: self.x, y: self.y, x1: self.x1, y1: overlap.y },
+            Rectangle { x: self.x, y: overlap.y1, x1: self.x1, y1: self.y1 },
+            Rectangle { x: self.x, y: overlap.y, x1: overlap.x, y1: overlap.y1 },
+            Rectangle { x: overlap.x1, y: overlap.y, x1: self.x1, y1: overlap.y1 },
+        ];
+        pieces.into_iter().filter(|r| !r.is_empty()).collect()
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct Claim {
     id: i64,
     rect: Rectangle,
 }
 
-fn num_overlaps(claims: &Vec<Claim>) -> usize {
+// Number of claims covering each claimed square inch.
+fn coverage_counts(claims: &[Claim]) -> HashMap<Point, i64> {
     let mut square_claims = HashMap::new();
     for claim in claims {
         for x in claim.rect.x..claim.rect.x1 {
             for y in claim.rect.y..claim.rect.y1 {
-                let pt = Point { x: x, y: y };
-                let count = match square_claims.get(&pt) {
-                    Some(n) => *n,
-                    None => 0,
-                };
+                *square_claims.entry(Point { x, y }).or_insert(0) += 1;
+            }
+        }
+    }
+    square_claims
+}
+
+// Kept as the reference implementation for `num_overlaps`.
+#[cfg(test)]
+fn num_overlaps_cells(claims: &[Claim]) -> usize {
+    coverage_counts(claims).values().filter(|&&count| count > 1).count()
+}
+
+// The smallest rectangle containing every claim.
+fn claims_bounds(claims: &[Claim]) -> Rectangle {
+    claims.iter().fold(Rectangle::new(0, 0, 0, 0), |b, c| b.union(&c.rect))
+}
+
+// Draws the coverage of each square inch inside `bounds`, one row per line: `.` for
+// unclaimed, the number of claims for 1 to 9, and `*` for more.  Squares of the
+// `highlight` claim are drawn as `#`, or `X` where another claim overlaps it.
+fn render_ascii(claims: &[Claim], bounds: &Rectangle, highlight: Option<i64>) -> String {
+    let counts = coverage_counts(claims);
+    let highlighted = claims.iter().find(|c| Some(c.id) == highlight).map(|c| &c.rect);
+
+    let mut out = String::new();
+    for y in bounds.y..bounds.y1 {
+        for x in bounds.x..bounds.x1 {
+            let pt = Point { x, y };
+            let count = counts.get(&pt).cloned().unwrap_or(0);
+            let c = match (highlighted.is_some_and(|r| r.contains(&pt)), count) {
+                (true, 1) => '#',
+                (true, _) => 'X',
+                (false, 0) => '.',
+                (false, n) if n < 10 => (b'0' + n as u8) as char,
+                (false, _) => '*',
+            };
+            out.push(c);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Writes the coverage inside `bounds` as a binary greyscale PGM, from white for unclaimed
+// squares to black for the most contested ones.  With a `highlight` claim it writes a PPM
+// instead, with that claim's squares tinted red.
+fn write_image<W: Write>(
+    w: &mut W,
+    claims: &[Claim],
+    bounds: &Rectangle,
+    highlight: Option<i64>,
+) -> Result<(), Error> {
+    let counts = coverage_counts(claims);
+    let max = cmp::max(1, counts.values().cloned().max().unwrap_or(0));
+    let highlighted = claims.iter().find(|c| Some(c.id) == highlight).map(|c| &c.rect);
+
+    let magic = if highlighted.is_some() { "P6" } else { "P5" };
+    write!(w, "{}\n{} {}\n255\n", magic, bounds.width(), bounds.height())?;
+
+    let mut data = Vec::new();
+    for y in bounds.y..bounds.y1 {
+        for x in bounds.x..bounds.x1 {
+            let pt = Point { x, y };
+            let count = counts.get(&pt).cloned().unwrap_or(0);
+            let grey = (255 - count * 255 / max) as u8;
+            match highlighted {
+                None => data.push(grey),
+                Some(r) if r.contains(&pt) => data.extend_from_slice(&[255, grey / 2, grey / 2]),
+                Some(_) => data.extend_from_slice(&[grey, grey, grey]),
+            }
+        }
+    }
+    w.write_all(&data)
+}
+
+// Splits the union of [y, y1) intervals into consecutive (y, y1, depth) segments, where
+// depth is the number of intervals covering the segment.  Gaps are left out.  The
+// intervals are given by their edges, (y, 1) where one starts and (y1, -1) where it ends,
+// sorted.
+fn depth_segments(edges: &[(i64, i64)]) -> Vec<(i64, i64, i64)> {
+    let mut segments: Vec<(i64, i64, i64)> = Vec::new();
+    let mut depth = 0;
+    let mut last = 0;
+    for &(y, delta) in edges {
+        if depth > 0 && y > last {
+            match segments.last_mut() {
+                Some(prev) if prev.1 == last && prev.2 == depth => prev.1 = y,
+                _ => segments.push((last, y, depth)),
+            }
+        }
+        depth += delta;
+        last = y;
+    }
+    segments
+}
 
-                square_claims.insert(pt, count + 1);
+// Total length of the parts of [y, y1) intervals that are covered at least `min_depth` times.
+// The intervals are given by their sorted edges, as for `depth_segments`.
+fn covered_length(edges: &[(i64, i64)], min_depth: i64) -> i64 {
+    depth_segments(edges).iter().filter(|s| s.2 >= min_depth).map(|s| s.1 - s.0).sum()
+}
+
+// Sweeps a vertical line across the rectangles' left and right edges.  Between two
+// consecutive edges the set of rectangles under the line doesn't change, so `f` is
+// called once per such slab with its [x, x1) range and the sorted edges of those
+// rectangles' y intervals (see `depth_segments`).  The edges are kept sorted as
+// rectangles enter and leave the line rather than being rebuilt for every slab.
+fn sweep<F: FnMut(i64, i64, &[(i64, i64)])>(rects: &[Rectangle], mut f: F) {
+    // (x, enter or leave, y, y1), with leaving sorted first.
+    let mut events: Vec<(i64, i64, i64, i64)> = Vec::new();
+    for r in rects.iter().filter(|r| !r.is_empty()) {
+        events.push((r.x, 1, r.y, r.y1));
+        events.push((r.x1, -1, r.y, r.y1));
+    }
+    events.sort();
+
+    let mut active: Vec<(i64, i64)> = Vec::new();
+    let mut i = 0;
+    while i < events.len() {
+        let x = events[i].0;
+        while i < events.len() && events[i].0 == x {
+            let (_, change, y, y1) = events[i];
+            for &edge in &[(y, 1), (y1, -1)] {
+                if change > 0 {
+                    let pos = active.partition_point(|&e| e < edge);
+                    active.insert(pos, edge);
+                } else {
+                    let pos = active.binary_search(&edge).unwrap();
+                    active.remove(pos);
+                }
             }
+            i += 1;
+        }
+        if let Some(&(x1, _, _, _)) = events.get(i) {
+            f(x, x1, &active);
         }
     }
+}
+
+fn covered_area(claims: &[Claim], min_depth: i64) -> i64 {
+    let rects: Vec<Rectangle> = claims.iter().map(|c| c.rect.clone()).collect();
+
+    let mut area = 0;
+    sweep(&rects, |x, x1, edges| area += (x1 - x) * covered_length(edges, min_depth));
+    area
+}
+
+fn num_overlaps(claims: &[Claim]) -> usize {
+    covered_area(claims, 2) as usize
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DepthHistogram {
+    // `areas[k]` is the number of square inches covered by exactly k claims.
+    areas: Vec<i64>,
+    max_depth: usize,
+    // Disjoint rectangles covering exactly the squares at `max_depth`.
+    max_regions: Vec<Rectangle>,
+}
+
+impl DepthHistogram {
+    fn new(bounds: &Rectangle) -> DepthHistogram {
+        DepthHistogram { areas: vec![bounds.area()], max_depth: 0, max_regions: Vec::new() }
+    }
+
+    fn add(&mut self, region: Rectangle, depth: usize) {
+        if self.areas.len() <= depth {
+            self.areas.resize(depth + 1, 0);
+        }
+        self.areas[depth] += region.area();
+        self.areas[0] -= region.area();
+
+        if depth > self.max_depth {
+            self.max_depth = depth;
+            self.max_regions.clear();
+        }
+        if depth == self.max_depth {
+            self.max_regions.push(region);
+        }
+    }
+
+    fn finish(mut self, bounds: &Rectangle) -> DepthHistogram {
+        if self.max_depth == 0 && !bounds.is_empty() {
+            self.max_regions = vec![bounds.clone()];
+        }
+        self
+    }
+}
+
+// Histogram of claim coverage for the squares inside `bounds`, using the same sweep as
+// `num_overlaps`.
+fn coverage_histogram(claims: &[Claim], bounds: &Rectangle) -> DepthHistogram {
+    let clipped: Vec<Rectangle> = claims.iter().filter_map(|c| c.rect.intersect(bounds)).collect();
+
+    let mut hist = DepthHistogram::new(bounds);
+    sweep(&clipped, |x, x1, edges| {
+        for (y, y1, depth) in depth_segments(edges) {
+            hist.add(Rectangle { x, y, x1, y1 }, depth as usize);
+        }
+    });
+    hist.finish(bounds)
+}
+
+// Same as `coverage_histogram`, but built one square at a time from `coverage_counts`.
+// Maximum depth regions are reported as individual squares.
+#[cfg(test)]
+fn coverage_histogram_cells(claims: &[Claim], bounds: &Rectangle) -> DepthHistogram {
+    let counts = coverage_counts(claims);
 
-    let mut overlaps = 0;
-    for (_, count) in square_claims {
-        if count > 1 {
-            overlaps += 1;
+    let mut hist = DepthHistogram::new(bounds);
+    for x in bounds.x..bounds.x1 {
+        for y in bounds.y..bounds.y1 {
+            if let Some(&n) = counts.get(&Point { x, y }) {
+                hist.add(Rectangle::new(x, y, 1, 1), n as usize);
+            }
         }
     }
-    overlaps
+    hist.finish(bounds)
 }
 
-fn find_non_overlapping(claims: &Vec<Claim>) -> Option<i64> {
-    'outer: for c0 in claims {
-        'inner: for c1 in claims {
-            if c0 == c1 {
-                continue 'inner;
+// Claims covering more grid cells than this are kept out of the grid and checked by
+// every query instead, so a single huge claim can't blow up the size of the index.
+const MAX_CELLS_PER_CLAIM: i64 = 64;
+
+// Buckets claims into a grid of square cells so that intersection queries only need to
+// look at claims sharing a cell with the query rectangle.
+struct ClaimIndex<'a> {
+    claims: &'a [Claim],
+    cell_size: i64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    large: Vec<usize>,
+}
+
+impl<'a> ClaimIndex<'a> {
+    pub fn new(claims: &'a [Claim]) -> ClaimIndex<'a> {
+        // Cells around the size of a typical claim keep both the number of cells per
+        // claim and the number of claims per cell small.  The median isn't thrown off by a
+        // few outsized claims the way the mean would be.
+        let mut sizes: Vec<i64> =
+            claims.iter().map(|c| (c.rect.width() + c.rect.height()) / 2).collect();
+        sizes.sort();
+        let cell_size = cmp::max(1, sizes.get(sizes.len() / 2).cloned().unwrap_or(1));
+
+        let mut index = ClaimIndex { claims, cell_size, cells: HashMap::new(), large: Vec::new() };
+        for (i, claim) in claims.iter().enumerate() {
+            if index.num_cells(&claim.rect) > MAX_CELLS_PER_CLAIM {
+                index.large.push(i);
+                continue;
             }
-            if let Some(_) = c0.rect.intersect(&c1.rect) {
-                continue 'outer;
+            for cell in index.cells_for(&claim.rect) {
+                index.cells.entry(cell).or_default().push(i);
             }
         }
-        return Some(c0.id);
+        index
+    }
+
+    // The inclusive range of cells (x0, y0, x1, y1) touched by `r`.
+    fn cell_range(&self, r: &Rectangle) -> Option<(i64, i64, i64, i64)> {
+        if r.is_empty() {
+            return None;
+        }
+
+        Some((
+            r.x.div_euclid(self.cell_size),
+            r.y.div_euclid(self.cell_size),
+            (r.x1 - 1).div_euclid(self.cell_size),
+            (r.y1 - 1).div_euclid(self.cell_size),
+        ))
+    }
+
+    fn num_cells(&self, r: &Rectangle) -> i64 {
+        self.cell_range(r).map_or(0, |(cx0, cy0, cx1, cy1)| (cx1 - cx0 + 1) * (cy1 - cy0 + 1))
+    }
+
+    fn cells_for(&self, r: &Rectangle) -> Vec<(i64, i64)> {
+        let (cx0, cy0, cx1, cy1) = match self.cell_range(r) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+
+        let mut cells = Vec::new();
+        for cx in cx0..=cx1 {
+            for cy in cy0..=cy1 {
+                cells.push((cx, cy));
+            }
+        }
+        cells
+    }
+
+    // The grid members sharing a cell with `r`.  A query touching more cells than are
+    // occupied walks the occupied cells instead.
+    fn grid_candidates(&self, r: &Rectangle) -> Vec<usize> {
+        if self.num_cells(r) <= self.cells.len() as i64 {
+            return self
+                .cells_for(r)
+                .iter()
+                .filter_map(|cell| self.cells.get(cell))
+                .flat_map(|members| members.iter().cloned())
+                .collect();
+        }
+
+        let (cx0, cy0, cx1, cy1) = match self.cell_range(r) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+        self.cells
+            .iter()
+            .filter(|&(&(cx, cy), _)| cx0 <= cx && cx <= cx1 && cy0 <= cy && cy <= cy1)
+            .flat_map(|(_, members)| members.iter().cloned())
+            .collect()
+    }
+
+    // Indices (into the indexed claims) of the claims intersecting `r`, in ascending order.
+    fn query_indices(&self, r: &Rectangle) -> Vec<usize> {
+        let mut found: Vec<usize> = self
+            .grid_candidates(r)
+            .into_iter()
+            .chain(self.large.iter().cloned())
+            .filter(|&i| self.claims[i].rect.intersect(r).is_some())
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    #[cfg(test)]
+    pub fn query(&self, r: &Rectangle) -> Vec<&'a Claim> {
+        let claims = self.claims;
+        self.query_indices(r).into_iter().map(|i| &claims[i]).collect()
     }
-    None
+}
+
+// IDs of every claim that doesn't intersect any other claim, in input order.  Claims
+// without any area aren't really claiming anything, so they're never reported.
+fn find_all_non_overlapping(claims: &[Claim]) -> Vec<i64> {
+    let index = ClaimIndex::new(claims);
+    claims
+        .iter()
+        .enumerate()
+        .filter(|&(_, c)| !c.rect.is_empty())
+        .filter(|&(i, c)| index.query_indices(&c.rect).iter().all(|&j| j == i))
+        .map(|(_, c)| c.id)
+        .collect()
+}
+
+// Disjoint rectangles covering the parts of the `i`th claim that no other claim touches.
+fn uncontested_parts(claims: &[Claim], i: usize) -> Vec<Rectangle> {
+    let index = ClaimIndex::new(claims);
+    let mut parts = vec![claims[i].rect.clone()];
+    for j in index.query_indices(&claims[i].rect).into_iter().filter(|&j| j != i) {
+        parts = parts.iter().flat_map(|p| p.subtract(&claims[j].rect)).collect();
+    }
+    parts
+}
+
+fn find_non_overlapping(claims: &[Claim]) -> Option<i64> {
+    find_all_non_overlapping(claims).into_iter().next()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Conflict {
+    // Where the other claim is in the input, since IDs aren't guaranteed to be unique.
+    index: usize,
+    id: i64,
+    area: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ConflictGraph {
+    // The claims each claim intersects, in the same order as the input claims.  Each list
+    // is in input order too.
+    conflicts: Vec<Vec<Conflict>>,
+    // IDs of groups of claims connected through conflicts.  Claims without any conflicts
+    // are left out.  Each component is sorted and the list is ordered by first ID.
+    components: Vec<Vec<i64>>,
+}
+
+impl ConflictGraph {
+    // Ties go to the component with the lowest claim ID.
+    pub fn largest_component(&self) -> Option<&Vec<i64>> {
+        self.components.iter().fold(None, |best: Option<&Vec<i64>>, c| match best {
+            Some(b) if b.len() >= c.len() => Some(b),
+            _ => Some(c),
+        })
+    }
+}
+
+fn conflict_graph(claims: &[Claim]) -> ConflictGraph {
+    let index = ClaimIndex::new(claims);
+
+    let conflicts: Vec<Vec<Conflict>> = claims
+        .iter()
+        .enumerate()
+        .map(|(i, claim)| {
+            index
+                .query_indices(&claim.rect)
+                .into_iter()
+                .filter(|&j| j != i)
+                .map(|j| Conflict {
+                    index: j,
+                    id: claims[j].id,
+                    area: claims[j].rect.intersect(&claim.rect).map_or(0, |r| r.area()),
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut components = Vec::new();
+    let mut visited = vec![false; claims.len()];
+    for (i, found) in conflicts.iter().enumerate() {
+        if found.is_empty() || visited[i] {
+            continue;
+        }
+
+        let mut component = Vec::new();
+        let mut stack = vec![i];
+        visited[i] = true;
+        while let Some(cur) = stack.pop() {
+            component.push(claims[cur].id);
+            for c in &conflicts[cur] {
+                if !visited[c.index] {
+                    visited[c.index] = true;
+                    stack.push(c.index);
+                }
+            }
+        }
+        component.sort();
+        components.push(component);
+    }
+    components.sort();
+
+    ConflictGraph { conflicts, components }
+}
+
+// Claims mapped onto a grid whose lines are only the distinct claim edges.  Every
+// compressed cell is covered by the same set of claims, so the work depends on the
+// number of claims rather than on how big (or how far from the origin) they are: n
+// claims make at most 2n lines each way, however much fabric they cover.
+struct CompressedClaims {
+    xs: Vec<i64>,
+    ys: Vec<i64>,
+    // Number of claims covering [xs[i], xs[i + 1]) by [ys[j], ys[j + 1]), stored at
+    // `i * ys.len() + j`.
+    depth: Vec<u32>,
+    // Number of cells covered more than once among the cells before (i, j) in both
+    // directions, stored at `i * (ys.len() + 1) + j`.
+    contested: Vec<u32>,
+    claims: Vec<Claim>,
+}
+
+impl CompressedClaims {
+    // Claims without any area can't cover or overlap anything, so they're left out.
+    pub fn new(claims: &[Claim]) -> CompressedClaims {
+        let claims: Vec<Claim> = claims.iter().filter(|c| !c.rect.is_empty()).cloned().collect();
+
+        let mut xs: Vec<i64> = claims.iter().flat_map(|c| vec![c.rect.x, c.rect.x1]).collect();
+        xs.sort();
+        xs.dedup();
+        let mut ys: Vec<i64> = claims.iter().flat_map(|c| vec![c.rect.y, c.rect.y1]).collect();
+        ys.sort();
+        ys.dedup();
+
+        // 2D difference array: mark each claim's corners, then prefix sum in both
+        // directions to get the depth of every cell.
+        let (w, h) = (xs.len(), ys.len());
+        let mut depth = vec![0i64; w * h];
+        for c in &claims {
+            let (i0, i1) = (Self::find(&xs, c.rect.x), Self::find(&xs, c.rect.x1));
+            let (j0, j1) = (Self::find(&ys, c.rect.y), Self::find(&ys, c.rect.y1));
+            depth[i0 * h + j0] += 1;
+            depth[i1 * h + j0] -= 1;
+            depth[i0 * h + j1] -= 1;
+            depth[i1 * h + j1] += 1;
+        }
+        for i in 0..w {
+            for j in 0..h {
+                if i > 0 {
+                    depth[i * h + j] += depth[(i - 1) * h + j];
+                }
+                if j > 0 {
+                    depth[i * h + j] += depth[i * h + j - 1];
+                }
+                if i > 0 && j > 0 {
+                    depth[i * h + j] -= depth[(i - 1) * h + j - 1];
+                }
+            }
+        }
+        let depth: Vec<u32> = depth.into_iter().map(|d| d as u32).collect();
+
+        let mut contested = vec![0u32; (w + 1) * (h + 1)];
+        for i in 0..w {
+            for j in 0..h {
+                contested[(i + 1) * (h + 1) + j + 1] = contested[i * (h + 1) + j + 1]
+                    + contested[(i + 1) * (h + 1) + j]
+                    - contested[i * (h + 1) + j]
+                    + if depth[i * h + j] > 1 { 1 } else { 0 };
+            }
+        }
+
+        CompressedClaims { xs, ys, depth, contested, claims }
+    }
+
+    fn find(coords: &[i64], v: i64) -> usize {
+        coords.binary_search(&v).unwrap()
+    }
+
+    // Calls `f` with the real area and depth of every covered compressed cell.
+    fn for_each_cell<F: FnMut(i64, u32)>(&self, mut f: F) {
+        let h = self.ys.len();
+        for i in 0..self.xs.len().saturating_sub(1) {
+            for j in 0..h.saturating_sub(1) {
+                let d = self.depth[i * h + j];
+                if d > 0 {
+                    f((self.xs[i + 1] - self.xs[i]) * (self.ys[j + 1] - self.ys[j]), d);
+                }
+            }
+        }
+    }
+
+    pub fn covered_area(&self, min_depth: i64) -> i64 {
+        let mut area = 0;
+        self.for_each_cell(|a, d| {
+            if d as i64 >= min_depth {
+                area += a;
+            }
+        });
+        area
+    }
+
+    // `areas[k]` is the area covered by exactly k claims, for k >= 1.
+    pub fn depth_areas(&self) -> Vec<i64> {
+        let mut areas = vec![0];
+        self.for_each_cell(|a, d| {
+            let d = d as usize;
+            if areas.len() <= d {
+                areas.resize(d + 1, 0);
+            }
+            areas[d] += a;
+        });
+        areas
+    }
+
+    // Number of claims covering the square inch at `pt`.
+    pub fn depth_at(&self, pt: &Point) -> i64 {
+        // Index of the last grid line at or before the point.  Points before the first
+        // line or at or after the last one aren't covered at all.
+        let last_before = |coords: &[i64], v: i64| match coords.binary_search(&v) {
+            Ok(i) if i + 1 < coords.len() => Some(i),
+            Err(i) if i > 0 && i < coords.len() => Some(i - 1),
+            _ => None,
+        };
+        match (last_before(&self.xs, pt.x), last_before(&self.ys, pt.y)) {
+            (Some(i), Some(j)) => self.depth[i * self.ys.len() + j] as i64,
+            _ => 0,
+        }
+    }
+
+    // A claim overlaps nothing iff none of the cells it spans is covered more than once.
+    pub fn non_overlapping(&self) -> Vec<i64> {
+        let h = self.ys.len() + 1;
+        self.claims
+            .iter()
+            .filter(|c| {
+                let (i0, i1) = (Self::find(&self.xs, c.rect.x), Self::find(&self.xs, c.rect.x1));
+                let (j0, j1) = (Self::find(&self.ys, c.rect.y), Self::find(&self.ys, c.rect.y1));
+                let c = &self.contested;
+                c[i1 * h + j1] + c[i0 * h + j0] == c[i0 * h + j1] + c[i1 * h + j0]
+            })
+            .map(|c| c.id)
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Violation {
+    DuplicateId { first_line: usize },
+    DuplicateClaim { first_line: usize },
+    Empty,
+    OutOfBounds,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Problem {
+    line: usize,
+    id: i64,
+    violation: Violation,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: claim #{} ", self.line, self.id)?;
+        match self.violation {
+            Violation::DuplicateId { first_line } => {
+                write!(f, "reuses the ID from line {}", first_line)
+            }
+            Violation::DuplicateClaim { first_line } => {
+                write!(f, "is the same area as line {}", first_line)
+            }
+            Violation::Empty => write!(f, "has no area"),
+            Violation::OutOfBounds => write!(f, "extends past the edge of the fabric"),
+        }
+    }
+}
+
+// Checks claims, which are expected in input order with one claim per line, for reused
+// IDs, repeated areas, empty areas and areas that don't fit on `fabric`.
+fn validate_claims(claims: &[Claim], fabric: &Rectangle) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut ids: HashMap<i64, usize> = HashMap::new();
+    let mut rects: HashMap<&Rectangle, usize> = HashMap::new();
+
+    for (i, claim) in claims.iter().enumerate() {
+        let line = i + 1;
+        let mut report = |violation| problems.push(Problem { line, id: claim.id, violation });
+
+        match ids.get(&claim.id) {
+            Some(&first_line) => report(Violation::DuplicateId { first_line }),
+            None => {
+                ids.insert(claim.id, line);
+            }
+        }
+        match rects.get(&claim.rect) {
+            Some(&first_line) => report(Violation::DuplicateClaim { first_line }),
+            None => {
+                rects.insert(&claim.rect, line);
+            }
+        }
+        if claim.rect.is_empty() {
+            report(Violation::Empty);
+        } else if claim.rect.intersect(fabric) != Some(claim.rect.clone()) {
+            report(Violation::OutOfBounds);
+        }
+    }
+
+    problems
+}
+
+// Returns the problems with the claims.  In strict mode any problem is an error instead.
+fn check_claims(claims: &[Claim], fabric: &Rectangle, strict: bool) -> Result<Vec<Problem>, Error> {
+    let problems = validate_claims(claims, fabric);
+    if strict && !problems.is_empty() {
+        let report: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        return Err(Error::new(ErrorKind::InvalidData, report.join("\n")));
+    }
+    Ok(problems)
+}
+
+// How long `optimize_placement` may search for.  It stops at whichever limit comes first.
+struct Budget {
+    iterations: usize,
+    time: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Placement {
+    claims: Vec<Claim>,
+    // IDs of the claims that ended up somewhere other than where they were asked for.
+    moved: Vec<i64>,
+    overlap: usize,
+}
+
+// xorshift64*; plenty for picking candidate positions and keeps runs reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform-ish value in [0, n].
+    fn upto(&mut self, n: i64) -> i64 {
+        if n <= 0 {
+            0
+        } else {
+            (self.next() % (n as u64 + 1)) as i64
+        }
+    }
+}
+
+fn move_claim(claim: &Claim, x: i64, y: i64) -> Claim {
+    Claim { id: claim.id, rect: Rectangle::new(x, y, claim.rect.width(), claim.rect.height()) }
+}
+
+// Packs the claims into rows, tallest first, starting a new row whenever the current one
+// is full.  Claims that run off the bottom are stacked at the last row's height and left
+// for the search to sort out.
+fn shelf_layout(claims: &[Claim], width: i64, height: i64) -> Vec<Claim> {
+    let mut order: Vec<usize> = (0..claims.len()).collect();
+    order.sort_by_key(|&i| cmp::Reverse(claims[i].rect.height()));
+
+    let mut layout = claims.to_vec();
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+    for i in order {
+        let r = &claims[i].rect;
+        if x + r.width() > width && x > 0 {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        let top = cmp::max(0, cmp::min(y, height - r.height()));
+        layout[i] = move_claim(&claims[i], x, top);
+        x += r.width();
+        row_height = cmp::max(row_height, r.height());
+    }
+    layout
+}
+
+// Looks for positions on a `width` x `height` fabric that keep each claim's size but
+// minimise the overlapping area, as measured by `num_overlaps`.  Starts from the better
+// of the requested layout (clamped onto the fabric) and a simple row packing, then
+// repeatedly tries moving one claim to a random spot, keeping moves that don't make
+// things worse.
+fn optimize_placement(
+    claims: &[Claim],
+    width: i64,
+    height: i64,
+    budget: &Budget,
+    seed: u64,
+) -> Placement {
+    let start = Instant::now();
+    let mut rng = Rng(seed | 1);
+
+    let clamped: Vec<Claim> = claims
+        .iter()
+        .map(|c| {
+            let x = cmp::max(0, cmp::min(c.rect.x, width - c.rect.width()));
+            let y = cmp::max(0, cmp::min(c.rect.y, height - c.rect.height()));
+            move_claim(c, x, y)
+        })
+        .collect();
+    let shelved = shelf_layout(claims, width, height);
+
+    let (clamped_overlap, shelved_overlap) = (num_overlaps(&clamped), num_overlaps(&shelved));
+    let (mut layout, mut overlap) = if shelved_overlap < clamped_overlap {
+        (shelved, shelved_overlap)
+    } else {
+        (clamped, clamped_overlap)
+    };
+
+    for _ in 0..budget.iterations {
+        if overlap == 0 || layout.is_empty() || budget.time.is_some_and(|t| start.elapsed() >= t) {
+            break;
+        }
+
+        let i = rng.upto(layout.len() as i64 - 1) as usize;
+        let old = layout[i].clone();
+        let x = rng.upto(width - old.rect.width());
+        let y = rng.upto(height - old.rect.height());
+        layout[i] = move_claim(&old, x, y);
+
+        let candidate = num_overlaps(&layout);
+        if candidate <= overlap {
+            overlap = candidate;
+        } else {
+            layout[i] = old;
+        }
+    }
+
+    let moved =
+        claims.iter().zip(&layout).filter(|(a, b)| a.rect != b.rect).map(|(a, _)| a.id).collect();
+    Placement { claims: layout, moved, overlap }
 }
 
 fn parse_claim(s: &str) -> Result<Claim, text_io::Error> {
@@ -94,7 +899,19 @@ fn parse_claim(s: &str) -> Result<Claim, text_io::Error> {
     let w: i64;
     let h: i64;
     try_scan!(s.bytes() => "#{} @ {},{}: {}x{}", id, x, y, w, h);
-    Ok(Claim { id: id, rect: Rectangle::new(x, y, w, h) })
+    Ok(Claim { id, rect: Rectangle::new(x, y, w, h) })
+}
+
+// Parses a fabric size given as `<width>x<height>`.
+fn parse_fabric(s: &str) -> Result<Rectangle, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Bad fabric size \"{}\"", s));
+    let (w, h) = s.split_once('x').ok_or_else(invalid)?;
+    let w: i64 = w.parse().map_err(|_| invalid())?;
+    let h: i64 = h.parse().map_err(|_| invalid())?;
+    if w <= 0 || h <= 0 {
+        return Err(invalid());
+    }
+    Ok(Rectangle::new(0, 0, w, h))
 }
 
 fn read<R: Read>(io: R) -> Result<Vec<Claim>, Error> {
@@ -107,9 +924,120 @@ fn read<R: Read>(io: R) -> Result<Vec<Claim>, Error> {
 }
 
 fn main() -> Result<(), Error> {
+    // `day3 [--strict] [--coverage[=<x>,<y>]] [--conflicts] [--free=<claim id>]
+    //       [--ascii[=<claim id>]] [--histogram] [--optimize=<iterations>] [--fabric=<w>x<h>]
+    //       [<image> [claim id]]`
+    let (flags, args): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|a| a.starts_with("--"));
+    let flag = |name: &str| flags.iter().any(|f| f == name);
+    let value = |name: &str| flags.iter().find_map(|f| f.strip_prefix(name)?.strip_prefix('='));
+    let parse_id = |s: &str| s.parse::<i64>().map_err(|e| Error::new(ErrorKind::InvalidInput, e));
+    let known = [
+        "--strict",
+        "--coverage",
+        "--conflicts",
+        "--free",
+        "--ascii",
+        "--histogram",
+        "--optimize",
+        "--fabric",
+    ];
+    if let Some(f) = flags.iter().find(|f| !known.contains(&f.split('=').next().unwrap())) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Unrecognized option \"{}\"", f)));
+    }
+    let strict = flag("--strict");
+    let coverage = flag("--coverage") || value("--coverage").is_some();
+    let fabric = match value("--fabric") {
+        Some(size) => parse_fabric(size)?,
+        None => Rectangle::new(0, 0, FABRIC_SIZE, FABRIC_SIZE),
+    };
+    let mut args = args.into_iter();
+
     let input = read(File::open("input.txt")?)?;
+    for problem in check_claims(&input, &fabric, strict)? {
+        eprintln!("warning: {}", problem);
+    }
+
     println!("Pt 1 answer: {}", num_overlaps(&input));
     println!("Pt 2 answer: {:?}", find_non_overlapping(&input));
+
+    if coverage {
+        let compressed = CompressedClaims::new(&input);
+        println!("Claimed: {}", compressed.covered_area(1));
+        for (depth, area) in compressed.depth_areas().iter().enumerate().skip(1) {
+            println!("Covered by {} claim(s): {}", depth, area);
+        }
+        println!("Claims overlapping nothing: {:?}", compressed.non_overlapping());
+        if let Some(pt) = value("--coverage") {
+            let (x, y) = pt.split_once(',').ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, format!("Bad point \"{}\"", pt))
+            })?;
+            let pt = Point { x: parse_id(x)?, y: parse_id(y)? };
+            println!("Claims covering {},{}: {}", pt.x, pt.y, compressed.depth_at(&pt));
+        }
+    }
+
+    if flag("--histogram") {
+        let hist = coverage_histogram(&input, &fabric);
+        for (depth, area) in hist.areas.iter().enumerate() {
+            println!("Depth {}: {} square inches", depth, area);
+        }
+        println!("Deepest: {} claims in {} region(s)", hist.max_depth, hist.max_regions.len());
+    }
+
+    if flag("--conflicts") {
+        let graph = conflict_graph(&input);
+        let isolated = graph.conflicts.iter().filter(|c| c.is_empty()).count();
+        println!(
+            "{} groups of conflicting claims, {} claims on their own",
+            graph.components.len(),
+            isolated
+        );
+        if let Some(largest) = graph.largest_component() {
+            println!("Largest group ({} claims): {:?}", largest.len(), largest);
+        }
+    }
+
+    if let Some(id) = value("--free") {
+        let id = parse_id(id)?;
+        for (i, _) in input.iter().enumerate().filter(|(_, c)| c.id == id) {
+            let parts = uncontested_parts(&input, i);
+            let area: i64 = parts.iter().map(|p| p.area()).sum();
+            println!("Claim #{} on line {} has {} uncontested square inches:", id, i + 1, area);
+            for p in parts {
+                println!("  {}x{} at {},{}", p.width(), p.height(), p.x, p.y);
+            }
+        }
+    }
+
+    if let Some(iterations) = value("--optimize") {
+        let iterations = iterations.parse().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let budget = Budget { iterations, time: Some(Duration::from_secs(30)) };
+        let placement = optimize_placement(&input, fabric.width(), fabric.height(), &budget, 1);
+        println!(
+            "Best placement found: {} square inches overlapping, {} claims moved",
+            placement.overlap,
+            placement.moved.len()
+        );
+        for c in placement.claims.iter().filter(|c| placement.moved.contains(&c.id)) {
+            println!("  #{} to {},{}", c.id, c.rect.x, c.rect.y);
+        }
+    }
+
+    // Optionally draw the fabric as text, or write out a coverage heatmap of it.
+    if flag("--ascii") || value("--ascii").is_some() {
+        let highlight = value("--ascii").map(parse_id).transpose()?;
+        print!("{}", render_ascii(&input, &fabric.union(&claims_bounds(&input)), highlight));
+    }
+    if let Some(path) = args.next() {
+        let highlight = match args.next() {
+            Some(id) => Some(parse_id(&id)?),
+            None => None,
+        };
+        let mut f = BufWriter::new(File::create(&path)?);
+        write_image(&mut f, &input, &fabric.union(&claims_bounds(&input)), highlight)?;
+    }
+
     Ok(())
 }
 
@@ -130,6 +1058,110 @@ mod test {
             Some(Rectangle::new(2, 2, 1, 1)),
             Rectangle::new(1, 1, 5, 5).intersect(&Rectangle::new(2, 2, 1, 1))
         );
+        assert_eq!(
+            Some(Rectangle::new(3, 1, 2, 2)),
+            Rectangle::new(0, 0, 5, 3).intersect(&Rectangle::new(3, 1, 10, 4))
+        );
+
+        // Empty rectangles don't share any area with anything.
+        assert_eq!(None, Rectangle::new(2, 2, 0, 0).intersect(&Rectangle::new(1, 1, 5, 5)));
+        assert_eq!(None, Rectangle::new(2, 2, 0, 3).intersect(&Rectangle::new(1, 1, 5, 5)));
+    }
+
+    #[test]
+    fn rectangle_accessors_test() {
+        let r = Rectangle::new(-2, 3, 4, 5);
+        assert_eq!((4, 5, 20), (r.width(), r.height(), r.area()));
+        assert!(!r.is_empty());
+        assert!(Rectangle::new(0, 0, 0, 5).is_empty());
+        assert!(Rectangle::new(0, 0, -3, 5).is_empty());
+        assert_eq!(0, Rectangle::new(0, 0, -3, 5).area());
+
+        assert!(r.contains(&Point { x: -2, y: 3 }));
+        assert!(r.contains(&Point { x: 1, y: 7 }));
+        assert!(!r.contains(&Point { x: 2, y: 7 }));
+        assert!(!r.contains(&Point { x: 1, y: 8 }));
+        assert!(!r.contains(&Point { x: -3, y: 4 }));
+    }
+
+    #[test]
+    fn union_test() {
+        assert_eq!(
+            Rectangle::new(0, 0, 10, 6),
+            Rectangle::new(0, 2, 3, 4).union(&Rectangle::new(5, 0, 5, 1))
+        );
+        assert_eq!(
+            Rectangle::new(5, 0, 5, 1),
+            Rectangle::new(-100, -100, 0, 0).union(&Rectangle::new(5, 0, 5, 1))
+        );
+    }
+
+    #[test]
+    fn subtract_test() {
+        let r = Rectangle::new(0, 0, 10, 10);
+        assert_eq!(vec![r.clone()], r.subtract(&Rectangle::new(20, 20, 5, 5)));
+        assert_eq!(Vec::<Rectangle>::new(), r.subtract(&Rectangle::new(-1, -1, 12, 12)));
+        assert_eq!(
+            vec![
+                Rectangle::new(0, 0, 10, 2),
+                Rectangle::new(0, 5, 10, 5),
+                Rectangle::new(0, 2, 4, 3),
+                Rectangle::new(7, 2, 3, 3),
+            ],
+            r.subtract(&Rectangle::new(4, 2, 3, 3))
+        );
+        assert_eq!(vec![Rectangle::new(5, 0, 5, 10)], r.subtract(&Rectangle::new(-5, -5, 10, 20)));
+    }
+
+    use proptest::strategy::Strategy;
+
+    fn rectangle_strategy() -> impl Strategy<Value = Rectangle> {
+        (-20i64..20, -20i64..20, -2i64..15, -2i64..15)
+            .prop_map(|(x, y, w, h)| Rectangle::new(x, y, w, h))
+    }
+
+    proptest! {
+        #[test]
+        fn intersect_props(a in rectangle_strategy(), b in rectangle_strategy()) {
+            let i = a.intersect(&b);
+            prop_assert_eq!(&i, &b.intersect(&a));
+
+            let area = i.as_ref().map_or(0, |r| r.area());
+            prop_assert!(area <= a.area());
+            prop_assert!(area <= b.area());
+
+            if let Some(r) = i {
+                prop_assert_eq!(Some(r.clone()), r.intersect(&a));
+                prop_assert_eq!(Some(r.clone()), r.intersect(&b));
+            }
+        }
+
+        #[test]
+        fn union_props(a in rectangle_strategy(), b in rectangle_strategy()) {
+            let u = a.union(&b);
+            if !a.is_empty() || !b.is_empty() {
+                prop_assert_eq!(&u, &b.union(&a));
+            }
+            prop_assert!(u.area() >= a.area() && u.area() >= b.area());
+            for r in [&a, &b].iter().filter(|r| !r.is_empty()) {
+                prop_assert_eq!(Some((*r).clone()), u.intersect(r));
+            }
+        }
+
+        #[test]
+        fn subtract_props(a in rectangle_strategy(), b in rectangle_strategy()) {
+            let pieces = a.subtract(&b);
+            let overlap = a.intersect(&b).map_or(0, |r| r.area());
+            prop_assert_eq!(a.area() - overlap, pieces.iter().map(|r| r.area()).sum::<i64>());
+
+            for (i, p) in pieces.iter().enumerate() {
+                prop_assert_eq!(Some(p.clone()), p.intersect(&a));
+                prop_assert_eq!(None, p.intersect(&b));
+                for q in &pieces[i + 1..] {
+                    prop_assert_eq!(None, p.intersect(q));
+                }
+            }
+        }
     }
 
     #[test]
@@ -145,6 +1177,15 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_fabric_test() {
+        assert_eq!(Rectangle::new(0, 0, 1000, 1000), parse_fabric("1000x1000").unwrap());
+        assert_eq!(Rectangle::new(0, 0, 20, 5), parse_fabric("20x5").unwrap());
+        for bad in &["", "20", "20x", "x5", "0x5", "20x-5", "axb", "20x5x1"] {
+            assert!(parse_fabric(bad).is_err(), "{}", bad);
+        }
+    }
+
     #[test]
     fn overlaps_test() {
         let claims = vec![
@@ -173,4 +1214,414 @@ mod test {
         ];
         assert_eq!(None, find_non_overlapping(&claims));
     }
+
+    fn edges(intervals: &[(i64, i64)]) -> Vec<(i64, i64)> {
+        let mut edges: Vec<(i64, i64)> =
+            intervals.iter().flat_map(|&(y, y1)| vec![(y, 1), (y1, -1)]).collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn covered_length_test() {
+        assert_eq!(0, covered_length(&[], 1));
+        assert_eq!(10, covered_length(&edges(&[(0, 5), (3, 8), (10, 12)]), 1));
+        assert_eq!(2, covered_length(&edges(&[(0, 5), (3, 8), (10, 12)]), 2));
+        assert_eq!(0, covered_length(&edges(&[(0, 5), (5, 8)]), 2));
+    }
+
+    #[test]
+    fn overlaps_differential_test() {
+        let claims = read(File::open("input.txt").unwrap()).unwrap();
+        assert_eq!(num_overlaps_cells(&claims), num_overlaps(&claims));
+
+        let claims = vec![
+            parse_claim("#1 @ 0,0: 3x3").unwrap(),
+            parse_claim("#2 @ 0,0: 3x3").unwrap(),
+            parse_claim("#3 @ 1,1: 0x4").unwrap(),
+            parse_claim("#4 @ 2,2: 4x1").unwrap(),
+            parse_claim("#5 @ 5,0: 1x5").unwrap(),
+        ];
+        assert_eq!(num_overlaps_cells(&claims), num_overlaps(&claims));
+        assert_eq!(10, num_overlaps(&claims));
+    }
+
+    #[test]
+    fn claim_index_query_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+            parse_claim("#4 @ 40,40: 20x3").unwrap(),
+        ];
+        let index = ClaimIndex::new(&claims);
+
+        let ids = |r: Rectangle| index.query(&r).iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2], ids(Rectangle::new(3, 3, 2, 2)));
+        assert_eq!(vec![2, 3], ids(Rectangle::new(5, 4, 2, 2)));
+        assert_eq!(vec![4], ids(Rectangle::new(0, 41, 100, 1)));
+        assert_eq!(Vec::<i64>::new(), ids(Rectangle::new(-10, -10, 5, 5)));
+        assert_eq!(vec![1, 2, 3, 4], ids(Rectangle::new(-10, -10, 100, 100)));
+    }
+
+    #[test]
+    fn claim_index_skewed_sizes_test() {
+        let mut claims: Vec<Claim> = (0..100_000)
+            .map(|i| Claim { id: i + 1, rect: Rectangle::new(2 * (i % 300), 2 * (i / 300), 1, 1) })
+            .collect();
+        claims
+            .push(Claim { id: 0, rect: Rectangle::new(-1_000_000, 10_000, 1_000_000, 1_000_000) });
+        claims.push(Claim { id: -1, rect: Rectangle::new(0, 0, 1_000_000, 1_000_000) });
+
+        let index = ClaimIndex::new(&claims);
+        assert_eq!(vec![100_000, 100_001], index.large);
+        assert_eq!(
+            vec![1, -1],
+            index.query(&Rectangle::new(0, 0, 1, 1)).iter().map(|c| c.id).collect::<Vec<_>>()
+        );
+        assert_eq!(100_002, index.query(&Rectangle::new(-5, -5, 2_000_000, 2_000_000)).len());
+        assert_eq!(vec![0], find_all_non_overlapping(&claims));
+    }
+
+    #[test]
+    fn find_all_non_overlapping_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+            parse_claim("#4 @ 7,7: 1x1").unwrap(),
+            parse_claim("#5 @ 6,6: 2x2").unwrap(),
+            parse_claim("#6 @ 100,0: 1x1").unwrap(),
+        ];
+        assert_eq!(vec![6], find_all_non_overlapping(&claims));
+        assert!(find_all_non_overlapping(&[]).is_empty());
+
+        // Empty claims don't overlap anything, but they don't count either.
+        let claims = vec![
+            parse_claim("#1 @ 0,0: 4x4").unwrap(),
+            parse_claim("#2 @ 1,1: 0x0").unwrap(),
+            parse_claim("#3 @ 10,10: 1x1").unwrap(),
+        ];
+        assert_eq!(vec![1, 3], find_all_non_overlapping(&claims));
+        assert_eq!(Some(1), find_non_overlapping(&claims));
+        assert_eq!(vec![1, 3], CompressedClaims::new(&claims).non_overlapping());
+
+        let claims = read(File::open("input.txt").unwrap()).unwrap();
+        assert_eq!(vec![415], find_all_non_overlapping(&claims));
+    }
+
+    #[test]
+    fn uncontested_parts_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,4: 2x3").unwrap(),
+        ];
+
+        let parts = uncontested_parts(&claims, 0);
+        assert_eq!(12, parts.iter().map(|p| p.area()).sum::<i64>());
+        assert!(parts.iter().all(|p| p.intersect(&claims[1].rect).is_none()));
+
+        // #2 loses its corners to #1 and #3.
+        assert_eq!(16 - 4 - 2, uncontested_parts(&claims, 1).iter().map(|p| p.area()).sum::<i64>());
+        assert_eq!(vec![Rectangle::new(5, 5, 2, 2)], uncontested_parts(&claims, 2));
+    }
+
+    #[test]
+    fn conflict_graph_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,4: 2x3").unwrap(),
+            parse_claim("#4 @ 20,20: 2x2").unwrap(),
+            parse_claim("#5 @ 21,21: 2x2").unwrap(),
+            parse_claim("#6 @ 6,6: 1x1").unwrap(),
+            parse_claim("#7 @ 50,50: 1x1").unwrap(),
+        ];
+        let graph = conflict_graph(&claims);
+
+        assert_eq!(vec![Conflict { index: 1, id: 2, area: 4 }], graph.conflicts[0]);
+        assert_eq!(
+            vec![Conflict { index: 0, id: 1, area: 4 }, Conflict { index: 2, id: 3, area: 2 }],
+            graph.conflicts[1]
+        );
+        assert_eq!(
+            vec![Conflict { index: 1, id: 2, area: 2 }, Conflict { index: 5, id: 6, area: 1 }],
+            graph.conflicts[2]
+        );
+        assert_eq!(Vec::<Conflict>::new(), graph.conflicts[6]);
+
+        assert_eq!(vec![vec![1, 2, 3, 6], vec![4, 5]], graph.components);
+        assert_eq!(Some(&vec![1, 2, 3, 6]), graph.largest_component());
+
+        assert_eq!(None, conflict_graph(&[]).largest_component());
+
+        // Claims sharing an ID keep their own conflicts.
+        let claims = vec![
+            parse_claim("#1 @ 0,0: 2x2").unwrap(),
+            parse_claim("#2 @ 1,1: 2x2").unwrap(),
+            parse_claim("#1 @ 10,10: 2x2").unwrap(),
+            parse_claim("#3 @ 11,11: 1x1").unwrap(),
+        ];
+        let graph = conflict_graph(&claims);
+        assert_eq!(vec![Conflict { index: 1, id: 2, area: 1 }], graph.conflicts[0]);
+        assert_eq!(vec![Conflict { index: 3, id: 3, area: 1 }], graph.conflicts[2]);
+        assert_eq!(vec![vec![1, 2], vec![1, 3]], graph.components);
+    }
+
+    #[test]
+    fn render_ascii_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+        ];
+        let bounds = Rectangle::new(0, 0, 8, 8);
+
+        assert_eq!(
+            concat!(
+                "........\n",
+                "...1111.\n",
+                "...1111.\n",
+                ".112211.\n",
+                ".112211.\n",
+                ".111111.\n",
+                ".111111.\n",
+                "........\n",
+            ),
+            render_ascii(&claims, &bounds, None)
+        );
+        assert_eq!(
+            concat!(
+                "........\n",
+                "...1111.\n",
+                "...1111.\n",
+                ".##XX11.\n",
+                ".##XX11.\n",
+                ".####11.\n",
+                ".####11.\n",
+                "........\n",
+            ),
+            render_ascii(&claims, &bounds, Some(1))
+        );
+        assert_eq!(Rectangle::new(1, 1, 6, 6), claims_bounds(&claims));
+    }
+
+    #[test]
+    fn write_image_test() {
+        let claims =
+            vec![parse_claim("#1 @ 0,0: 2x1").unwrap(), parse_claim("#2 @ 1,0: 2x1").unwrap()];
+        let bounds = Rectangle::new(0, 0, 4, 1);
+
+        let mut pgm = Vec::new();
+        write_image(&mut pgm, &claims, &bounds, None).unwrap();
+        assert_eq!(b"P5\n4 1\n255\n\x80\x00\x80\xff".to_vec(), pgm);
+
+        let mut ppm = Vec::new();
+        write_image(&mut ppm, &claims, &bounds, Some(2)).unwrap();
+        assert_eq!(b"P6\n4 1\n255\n\x80\x80\x80\xff\x00\x00\xff\x40\x40\xff\xff\xff".to_vec(), ppm);
+    }
+
+    #[test]
+    fn depth_segments_test() {
+        assert_eq!(Vec::<(i64, i64, i64)>::new(), depth_segments(&[]));
+        assert_eq!(
+            vec![(0, 3, 1), (3, 5, 2), (5, 8, 1), (10, 12, 1)],
+            depth_segments(&edges(&[(0, 5), (3, 8), (10, 12), (4, 4)]))
+        );
+    }
+
+    fn region_points(regions: &[Rectangle]) -> Vec<(i64, i64)> {
+        let mut points: Vec<(i64, i64)> = regions
+            .iter()
+            .flat_map(|r| (r.x..r.x1).flat_map(move |x| (r.y..r.y1).map(move |y| (x, y))))
+            .collect();
+        points.sort();
+        points
+    }
+
+    #[test]
+    fn coverage_histogram_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+            parse_claim("#4 @ 3,4: 1x1").unwrap(),
+        ];
+        let bounds = Rectangle::new(0, 0, 8, 8);
+
+        let hist = coverage_histogram(&claims, &bounds);
+        assert_eq!(vec![64 - 32, 28, 3, 1], hist.areas);
+        assert_eq!(3, hist.max_depth);
+        assert_eq!(vec![Rectangle::new(3, 4, 1, 1)], hist.max_regions);
+
+        let cells = coverage_histogram_cells(&claims, &bounds);
+        assert_eq!(cells.areas, hist.areas);
+        assert_eq!(cells.max_depth, hist.max_depth);
+        assert_eq!(region_points(&cells.max_regions), region_points(&hist.max_regions));
+
+        // Only the part of the claims inside the bounds counts.
+        let hist = coverage_histogram(&claims, &Rectangle::new(0, 0, 4, 4));
+        assert_eq!(vec![11, 4, 1], hist.areas);
+        assert_eq!(vec![Rectangle::new(3, 3, 1, 1)], hist.max_regions);
+
+        let hist = coverage_histogram(&[], &Rectangle::new(0, 0, 2, 2));
+        assert_eq!(vec![4], hist.areas);
+        assert_eq!(vec![Rectangle::new(0, 0, 2, 2)], hist.max_regions);
+    }
+
+    #[test]
+    fn coverage_histogram_differential_test() {
+        let claims = read(File::open("input.txt").unwrap()).unwrap();
+        let bounds = Rectangle::new(0, 0, FABRIC_SIZE, FABRIC_SIZE);
+
+        let hist = coverage_histogram(&claims, &bounds);
+        let cells = coverage_histogram_cells(&claims, &bounds);
+        assert_eq!(cells.areas, hist.areas);
+        assert_eq!(cells.max_depth, hist.max_depth);
+        assert_eq!(region_points(&cells.max_regions), region_points(&hist.max_regions));
+        assert_eq!(num_overlaps(&claims) as i64, hist.areas[2..].iter().sum::<i64>());
+    }
+
+    #[test]
+    fn compressed_claims_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+            parse_claim("#4 @ 3,4: 1x1").unwrap(),
+            parse_claim("#5 @ 9,9: 0x3").unwrap(),
+        ];
+        let compressed = CompressedClaims::new(&claims);
+
+        assert_eq!(num_overlaps(&claims) as i64, compressed.covered_area(2));
+        assert_eq!(vec![0, 28, 3, 1], compressed.depth_areas());
+        assert_eq!(vec![3], compressed.non_overlapping());
+        assert_eq!(3, compressed.depth_at(&Point { x: 3, y: 4 }));
+        assert_eq!(2, compressed.depth_at(&Point { x: 4, y: 4 }));
+        assert_eq!(1, compressed.depth_at(&Point { x: 6, y: 6 }));
+        assert_eq!(0, compressed.depth_at(&Point { x: 7, y: 6 }));
+        assert_eq!(0, compressed.depth_at(&Point { x: 0, y: 0 }));
+
+        let empty = CompressedClaims::new(&[]);
+        assert_eq!(0, empty.covered_area(1));
+        assert_eq!(0, empty.depth_at(&Point { x: 0, y: 0 }));
+        assert!(empty.non_overlapping().is_empty());
+    }
+
+    #[test]
+    fn compressed_claims_huge_test() {
+        let claims = vec![
+            parse_claim("#1 @ 0,0: 1000000x1000000").unwrap(),
+            parse_claim("#2 @ -500000,-500000: 1000000x1000000").unwrap(),
+            parse_claim("#3 @ -2000000000,7: 3x4").unwrap(),
+        ];
+        let compressed = CompressedClaims::new(&claims);
+
+        assert_eq!(500_000 * 500_000, compressed.covered_area(2));
+        assert_eq!(2 * 1_000_000 * 1_000_000 - 500_000 * 500_000 + 12, compressed.covered_area(1));
+        assert_eq!(vec![3], compressed.non_overlapping());
+        assert_eq!(2, compressed.depth_at(&Point { x: 0, y: 0 }));
+        assert_eq!(1, compressed.depth_at(&Point { x: -1, y: -1 }));
+        assert_eq!(1, compressed.depth_at(&Point { x: -1_999_999_998, y: 10 }));
+    }
+
+    #[test]
+    fn compressed_claims_differential_test() {
+        let claims = read(File::open("input.txt").unwrap()).unwrap();
+        let compressed = CompressedClaims::new(&claims);
+
+        assert_eq!(num_overlaps(&claims) as i64, compressed.covered_area(2));
+        assert_eq!(find_all_non_overlapping(&claims), compressed.non_overlapping());
+        for (pt, &n) in &coverage_counts(&claims) {
+            assert_eq!(n, compressed.depth_at(pt));
+        }
+
+        let hist = coverage_histogram(&claims, &Rectangle::new(0, 0, FABRIC_SIZE, FABRIC_SIZE));
+        assert_eq!(hist.areas[1..], compressed.depth_areas()[1..]);
+    }
+
+    #[test]
+    fn validate_claims_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#1 @ 5,5: 2x2").unwrap(),
+            parse_claim("#4 @ 3,1: 4x4").unwrap(),
+            parse_claim("#5 @ 2,2: 0x4").unwrap(),
+            parse_claim("#6 @ 8,8: 3x1").unwrap(),
+            parse_claim("#7 @ -1,0: 1x1").unwrap(),
+            parse_claim("#8 @ 0,0: 10x10").unwrap(),
+        ];
+        let fabric = Rectangle::new(0, 0, 10, 10);
+
+        assert_eq!(
+            vec![
+                Problem { line: 3, id: 1, violation: Violation::DuplicateId { first_line: 1 } },
+                Problem { line: 4, id: 4, violation: Violation::DuplicateClaim { first_line: 2 } },
+                Problem { line: 5, id: 5, violation: Violation::Empty },
+                Problem { line: 6, id: 6, violation: Violation::OutOfBounds },
+                Problem { line: 7, id: 7, violation: Violation::OutOfBounds },
+            ],
+            validate_claims(&claims, &fabric)
+        );
+        assert_eq!(
+            "line 3: claim #1 reuses the ID from line 1",
+            validate_claims(&claims, &fabric)[0].to_string()
+        );
+
+        assert_eq!(5, check_claims(&claims, &fabric, false).unwrap().len());
+        let err = check_claims(&claims, &fabric, true).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(5, err.to_string().lines().count());
+
+        let input = read(File::open("input.txt").unwrap()).unwrap();
+        let fabric = Rectangle::new(0, 0, FABRIC_SIZE, FABRIC_SIZE);
+        assert!(check_claims(&input, &fabric, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn optimize_placement_test() {
+        let claims = vec![
+            parse_claim("#1 @ 1,3: 4x4").unwrap(),
+            parse_claim("#2 @ 3,1: 4x4").unwrap(),
+            parse_claim("#3 @ 5,5: 2x2").unwrap(),
+        ];
+        let budget = Budget { iterations: 100, time: None };
+
+        let placement = optimize_placement(&claims, 8, 8, &budget, 1);
+        assert_eq!(0, placement.overlap);
+        assert_eq!(0, num_overlaps(&placement.claims));
+        let fabric = Rectangle::new(0, 0, 8, 8);
+        assert!(validate_claims(&placement.claims, &fabric).is_empty());
+        for (a, b) in claims.iter().zip(&placement.claims) {
+            assert_eq!(a.id, b.id);
+            assert_eq!((a.rect.width(), a.rect.height()), (b.rect.width(), b.rect.height()));
+            assert_eq!(a.rect != b.rect, placement.moved.contains(&a.id));
+        }
+
+        // Already fine as asked for, so nothing moves.
+        let claims =
+            vec![parse_claim("#1 @ 0,0: 2x2").unwrap(), parse_claim("#2 @ 4,4: 2x2").unwrap()];
+        let placement = optimize_placement(&claims, 8, 8, &budget, 1);
+        assert_eq!(0, placement.overlap);
+        assert!(placement.moved.is_empty());
+    }
+
+    #[test]
+    fn optimize_placement_budget_test() {
+        // Two 3x3 claims can't avoid each other on a 4x4 fabric; the best they can do
+        // is share a 2x2 corner.
+        let claims =
+            vec![parse_claim("#1 @ 0,0: 3x3").unwrap(), parse_claim("#2 @ 0,0: 3x3").unwrap()];
+
+        let budget = Budget { iterations: 0, time: None };
+        assert_eq!(6, optimize_placement(&claims, 4, 4, &budget, 7).overlap);
+
+        let budget = Budget { iterations: 1000, time: Some(Duration::from_secs(0)) };
+        assert_eq!(6, optimize_placement(&claims, 4, 4, &budget, 7).overlap);
+
+        let budget = Budget { iterations: 1000, time: None };
+        assert_eq!(4, optimize_placement(&claims, 4, 4, &budget, 7).overlap);
+    }
 }
@@ -3,16 +3,19 @@ extern crate regex;
 #[macro_use]
 extern crate text_io;
 
-use chrono::{NaiveDateTime, Timelike};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
 use regex::Regex;
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::cmp::{self, Ordering};
+use std::collections::Bound::{Excluded, Included, Unbounded};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
 
 type DateTime = NaiveDateTime;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Action {
     BeginShift { id: i64 },
     Asleep,
@@ -43,7 +46,18 @@ impl PartialEq for Record {
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] ", self.time.format("%Y-%m-%d %H:%M"))?;
+        match self.action {
+            Action::BeginShift { id } => write!(f, "Guard #{} begins shift", id),
+            Action::Asleep => write!(f, "falls asleep"),
+            Action::Awake => write!(f, "wakes up"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct Span {
     id: i64,
     start: DateTime,
@@ -51,7 +65,7 @@ struct Span {
 }
 
 fn parse_time(s: &str) -> Result<DateTime, Error> {
-    let dt = DateTime::parse_from_str(&s, "%Y-%m-%d %H:%M");
+    let dt = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M");
     dt.map_err(|e| Error::new(ErrorKind::InvalidData, e))
 }
 
@@ -64,7 +78,7 @@ fn parse_begin(s: &str) -> Result<Action, text_io::Error> {
 }
 
 fn parse_action(s: &str) -> Result<Action, Error> {
-    if let Ok(action) = parse_begin(&s) {
+    if let Ok(action) = parse_begin(s) {
         return Ok(action);
     }
 
@@ -87,35 +101,201 @@ fn parse_record(s: &str) -> Result<Record, Error> {
         }
     };
 
-    let time = parse_time(&caps.get(1).unwrap().as_str())?;
-    let action = parse_action(&caps.get(2).unwrap().as_str())?;
-    Ok(Record { time: time, action: action })
+    let time = parse_time(caps.get(1).unwrap().as_str())?;
+    let action = parse_action(caps.get(2).unwrap().as_str())?;
+    Ok(Record { time, action })
+}
+
+// What to do with a guard who is still asleep when the next shift begins or the log ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnfinishedNap {
+    // Ignore the nap entirely.
+    Drop,
+    // The nap ends when the next guard begins their shift.  Naps still going at the end
+    // of the log are dropped.
+    EndAtShiftChange,
+    // The nap ends at the first 01:00 after it started, when the midnight hour is over.
+    EndOfMidnightHour,
+}
+
+fn parse_unfinished(s: &str) -> Result<UnfinishedNap, Error> {
+    match s {
+        "drop" => Ok(UnfinishedNap::Drop),
+        "shift-change" => Ok(UnfinishedNap::EndAtShiftChange),
+        "midnight-hour" => Ok(UnfinishedNap::EndOfMidnightHour),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unrecognized unfinished nap policy \"{}\"", s),
+        )),
+    }
+}
+
+fn end_of_midnight_hour(start: DateTime) -> DateTime {
+    let one_am = start.date().and_hms_opt(1, 0, 0).unwrap();
+    if start < one_am {
+        one_am
+    } else {
+        one_am + Duration::days(1)
+    }
+}
+
+#[cfg(test)]
+fn calc_spans(recs: &mut [Record]) -> Vec<Span> {
+    calc_spans_with(recs, UnfinishedNap::Drop)
 }
 
-fn calc_spans(recs: &mut Vec<Record>) -> Vec<Span> {
+fn calc_spans_with(recs: &mut [Record], unfinished: UnfinishedNap) -> Vec<Span> {
     let mut spans = Vec::new();
 
     let mut id = None;
     let mut start = None;
 
+    let close = |id: Option<i64>, start: Option<DateTime>, at: Option<DateTime>| -> Option<Span> {
+        let (id, start) = (id?, start?);
+        let end = match unfinished {
+            UnfinishedNap::Drop => return None,
+            UnfinishedNap::EndAtShiftChange => at?,
+            UnfinishedNap::EndOfMidnightHour => end_of_midnight_hour(start),
+        };
+        Some(Span { id, start, end })
+    };
+
     recs.sort();
-    for rec in recs {
+    for rec in recs.iter() {
         let time = rec.time;
         match rec.action {
-            Action::BeginShift { id: a } => id = Some(a),
+            Action::BeginShift { id: a } => {
+                spans.extend(close(id, start, Some(time)));
+                id = Some(a);
+                start = None;
+            }
             Action::Asleep => start = Some(time),
             Action::Awake => {
                 if let (Some(i), Some(s)) = (id, start) {
                     spans.push(Span { id: i, start: s, end: time });
                 }
+                start = None;
             }
         }
     }
+    spans.extend(close(id, start, None));
 
     spans
 }
 
-fn calc_sleep_min(spans: &Vec<Span>) -> HashMap<i64, i64> {
+// Each guard's time on duty, from the start of their shift until the next guard takes
+// over.  Nobody relieves the last guard, so their shift never ends.
+fn calc_shifts(recs: &mut [Record]) -> Vec<Span> {
+    let mut shifts: Vec<Span> = Vec::new();
+
+    recs.sort();
+    for rec in recs.iter() {
+        if let Action::BeginShift { id } = rec.action {
+            if let Some(prev) = shifts.last_mut() {
+                prev.end = rec.time;
+            }
+            shifts.push(Span { id, start: rec.time, end: DateTime::MAX });
+        }
+    }
+
+    shifts
+}
+
+// Spans sorted by start, alongside the latest end of any span up to each position.  Any
+// span containing a time T starts at or before T, and once the running latest end is at
+// or before T, no earlier span can contain it either, so lookups stop early.
+struct IntervalList {
+    spans: Vec<Span>,
+    max_end: Vec<DateTime>,
+}
+
+impl IntervalList {
+    fn new(spans: &[Span]) -> IntervalList {
+        let mut spans = spans.to_vec();
+        spans.sort_by_key(|s| s.start);
+
+        let mut max_end = Vec::with_capacity(spans.len());
+        for s in &spans {
+            let end = max_end.last().map_or(s.end, |&e| cmp::max(e, s.end));
+            max_end.push(end);
+        }
+
+        IntervalList { spans, max_end }
+    }
+
+    fn containing(&self, t: DateTime) -> Vec<&Span> {
+        let n = self.spans.partition_point(|s| s.start <= t);
+        let mut found: Vec<&Span> = (0..n)
+            .rev()
+            .take_while(|&i| self.max_end[i] > t)
+            .map(|i| &self.spans[i])
+            .filter(|s| t < s.end)
+            .collect();
+        found.reverse();
+        found
+    }
+}
+
+struct SleepIndex {
+    naps: IntervalList,
+    shifts: IntervalList,
+}
+
+impl SleepIndex {
+    pub fn new(recs: &mut [Record], unfinished: UnfinishedNap) -> SleepIndex {
+        let naps = calc_spans_with(recs, unfinished);
+        let shifts = calc_shifts(recs);
+        SleepIndex { naps: IntervalList::new(&naps), shifts: IntervalList::new(&shifts) }
+    }
+
+    // IDs of the guards asleep at `t`, in the order they fell asleep.
+    pub fn asleep_at(&self, t: DateTime) -> Vec<i64> {
+        self.naps.containing(t).iter().map(|s| s.id).collect()
+    }
+
+    pub fn on_duty_at(&self, t: DateTime) -> Option<i64> {
+        self.shifts.containing(t).last().map(|s| s.id)
+    }
+
+    // The most guards asleep at the same time, and every period during which that many
+    // were.  Periods are [start, end) and in time order.
+    pub fn max_concurrent_asleep(&self) -> (usize, Vec<(DateTime, DateTime)>) {
+        // Ends sort before starts at the same time since naps don't include their end.
+        let mut events: Vec<(DateTime, i64)> = Vec::new();
+        for s in &self.naps.spans {
+            events.push((s.start, 1));
+            events.push((s.end, -1));
+        }
+        events.sort();
+
+        let mut max = 0;
+        let mut periods: Vec<(DateTime, DateTime)> = Vec::new();
+        let mut depth = 0;
+        for (i, &(t, delta)) in events.iter().enumerate() {
+            depth += delta;
+            let next = match events.get(i + 1) {
+                Some(&(next, _)) if next > t => next,
+                _ => continue,
+            };
+
+            let d = depth as usize;
+            if d > max {
+                max = d;
+                periods.clear();
+            }
+            if d == max && d > 0 {
+                match periods.last_mut() {
+                    Some(last) if last.1 == t => last.1 = next,
+                    _ => periods.push((t, next)),
+                }
+            }
+        }
+
+        (max, periods)
+    }
+}
+
+fn calc_sleep_min(spans: &[Span]) -> HashMap<i64, i64> {
     let mut totals = HashMap::new();
 
     for span in spans {
@@ -131,76 +311,739 @@ fn calc_sleep_min(spans: &Vec<Span>) -> HashMap<i64, i64> {
     totals
 }
 
-fn get_sleep_histograms(spans: &Vec<Span>) -> HashMap<i64, [i64; 60]> {
+// Counts, per guard, how often they were asleep during each minute of the hour.  Every
+// elapsed minute of a span is counted, so naps crossing an hour or midnight, or lasting
+// more than an hour, land in the right buckets.
+fn get_sleep_histograms(spans: &[Span]) -> HashMap<i64, [i64; 60]> {
     let mut hists = HashMap::new();
 
     for s in spans {
-        if !hists.contains_key(&s.id) {
-            let h = [0; 60];
-            hists.insert(s.id, h);
-        }
-        let mut hist = hists.get_mut(&s.id).unwrap();
+        let hist = hists.entry(s.id).or_insert([0; 60]);
 
-        for m in s.start.minute()..s.end.minute() {
-            hist[m as usize] += 1;
+        let mut t = s.start;
+        while t < s.end {
+            hist[t.minute() as usize] += 1;
+            t += Duration::minutes(1);
         }
     }
 
     hists
 }
 
-fn find_most_slept_min(recs: &mut Vec<Record>) -> (i64, i64) {
-    let spans = calc_spans(recs);
-    let totals = calc_sleep_min(&spans);
-    let mut sleepiest = None;
-    let mut slept_max = 0;
-    for (id, slept) in totals {
-        if slept > slept_max {
-            sleepiest = Some(id);
-            slept_max = slept;
+// Shifts that begin before midnight are for the following day's midnight hour.
+fn shift_date(t: DateTime) -> NaiveDate {
+    if t.hour() >= 12 {
+        t.date() + Duration::days(1)
+    } else {
+        t.date()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChartOrder {
+    ByDate,
+    ByGuard,
+}
+
+// Limits which rows `render_chart` draws.  Date bounds are inclusive.
+#[derive(Debug, Default)]
+struct ChartFilter {
+    guard: Option<i64>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl ChartFilter {
+    fn matches(&self, date: NaiveDate, id: i64) -> bool {
+        self.guard.is_none_or(|g| g == id)
+            && self.from.is_none_or(|f| date >= f)
+            && self.to.is_none_or(|t| date <= t)
+    }
+}
+
+// Parses `[--by-guard] [--guard <id>] [--from <date>] [--to <date>]`, with dates given
+// as YYYY-MM-DD.
+fn parse_chart_args(args: &[&str]) -> Result<(ChartFilter, ChartOrder), Error> {
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+    let date = |s: &str| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    };
+
+    let mut filter = ChartFilter::default();
+    let mut order = ChartOrder::ByDate;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        if arg == "--by-guard" {
+            order = ChartOrder::ByGuard;
+            continue;
+        }
+
+        let value = *args.next().ok_or_else(|| invalid(format!("{} needs a value", arg)))?;
+        match arg {
+            "--guard" => {
+                let id =
+                    value.parse().map_err(|_| invalid(format!("Bad guard ID \"{}\"", value)))?;
+                filter.guard = Some(id);
+            }
+            "--from" => filter.from = Some(date(value)?),
+            "--to" => filter.to = Some(date(value)?),
+            _ => return Err(invalid(format!("Unrecognized chart option \"{}\"", arg))),
         }
     }
+    Ok((filter, order))
+}
+
+// Draws the spans like the puzzle does: one row per day and guard, with `#` for each
+// minute of the midnight hour they were asleep.
+fn render_chart(spans: &[Span], filter: &ChartFilter, order: ChartOrder) -> String {
+    let mut rows: BTreeMap<(NaiveDate, i64), [bool; 60]> = BTreeMap::new();
+    for s in spans {
+        let date = shift_date(s.start);
+        if !filter.matches(date, s.id) {
+            continue;
+        }
 
-    let id = sleepiest.unwrap();
+        let row = rows.entry((date, s.id)).or_insert([false; 60]);
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        for (m, asleep) in row.iter_mut().enumerate() {
+            let t = midnight + Duration::minutes(m as i64);
+            if s.start <= t && t < s.end {
+                *asleep = true;
+            }
+        }
+    }
 
-    let hists = get_sleep_histograms(&spans);
-    let hist = hists[&id];
+    let mut rows: Vec<((NaiveDate, i64), [bool; 60])> = rows.into_iter().collect();
+    if order == ChartOrder::ByGuard {
+        rows.sort_by_key(|&((date, id), _)| (id, date));
+    }
 
-    let mut max_min = 0;
-    let mut max_overlap = 0;
-    for m in 0..hist.len() {
-        let overlap = hist[m];
+    let id_width = rows.iter().map(|&((_, id), _)| format!("#{}", id).len()).max().unwrap_or(0);
+    let id_width = cmp::max(3, id_width);
+    let indent = " ".repeat(5 + 2 + id_width + 2);
+
+    let mut out = format!("{:<5}  {:<w$}  Minute\n", "Date", "ID", w = id_width);
+    out.push_str(&indent);
+    out.extend((0..60).map(|m| (b'0' + m / 10) as char));
+    out.push('\n');
+    out.push_str(&indent);
+    out.extend((0..60).map(|m| (b'0' + m % 10) as char));
+    out.push('\n');
+
+    for ((date, id), minutes) in rows {
+        let id = format!("#{}", id);
+        out.push_str(&format!("{}  {:<w$}  ", date.format("%m-%d"), id, w = id_width));
+        out.extend(minutes.iter().map(|&asleep| if asleep { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+// Everything the guard selection strategies get to look at for one guard.
+#[derive(Debug, PartialEq)]
+struct GuardSleep {
+    id: i64,
+    hist: [i64; 60],
+    total: i64,
+    naps: Vec<Span>,
+}
 
-        if overlap > max_overlap {
-            max_min = m;
-            max_overlap = overlap
+impl GuardSleep {
+    // Every minute tied for the most naps, in order.
+    fn most_slept_minutes(&self) -> Vec<i64> {
+        let max = self.hist.iter().cloned().max().unwrap_or(0);
+        (0..60).filter(|&m| self.hist[m as usize] == max).collect()
+    }
+
+    fn nights(&self) -> usize {
+        let dates: BTreeSet<NaiveDate> = self.naps.iter().map(|s| shift_date(s.start)).collect();
+        dates.len()
+    }
+
+    fn longest_nap(&self) -> i64 {
+        let lens = self.naps.iter().map(|s| s.end.signed_duration_since(s.start).num_minutes());
+        lens.max().unwrap_or(0)
+    }
+
+    // How spread out the minutes spent asleep are around the hour, from 0 when they're all
+    // the same minute to 1 when they're spread evenly.  Minutes are placed on a circle, so
+    // 59 and 0 are neighbours and naps across midnight count as tightly bunched.  This is
+    // one minus the mean resultant length of the minutes' angles.
+    fn minute_spread(&self) -> f64 {
+        let n: i64 = self.hist.iter().sum();
+        if n == 0 {
+            return 0.0;
+        }
+
+        let (mut x, mut y) = (0.0, 0.0);
+        for (m, &c) in self.hist.iter().enumerate() {
+            let angle = m as f64 * std::f64::consts::TAU / 60.0;
+            x += c as f64 * angle.cos();
+            y += c as f64 * angle.sin();
         }
+        1.0 - x.hypot(y) / n as f64
+    }
+}
+
+fn guard_sleep(spans: &[Span]) -> BTreeMap<i64, GuardSleep> {
+    let mut guards: BTreeMap<i64, GuardSleep> = BTreeMap::new();
+    let totals = calc_sleep_min(spans);
+    for (id, hist) in get_sleep_histograms(spans) {
+        guards.insert(id, GuardSleep { id, hist, total: totals[&id], naps: Vec::new() });
+    }
+    for s in spans {
+        guards.get_mut(&s.id).unwrap().naps.push(s.clone());
+    }
+    guards
+}
+
+// A way of ranking guards.  The guard with the highest score gets picked, along with the
+// minute they were most often asleep.
+trait Strategy {
+    fn name(&self) -> &'static str;
+    fn score(&self, guard: &GuardSleep) -> f64;
+}
+
+// Part 1: the guard with the most minutes asleep.
+struct MostMinutesAsleep;
+
+impl Strategy for MostMinutesAsleep {
+    fn name(&self) -> &'static str {
+        "most minutes asleep"
+    }
+
+    fn score(&self, guard: &GuardSleep) -> f64 {
+        guard.total as f64
+    }
+}
+
+// Part 2: the guard most frequently asleep on the same minute.
+struct MostFrequentMinute;
+
+impl Strategy for MostFrequentMinute {
+    fn name(&self) -> &'static str {
+        "most frequently asleep on the same minute"
+    }
+
+    fn score(&self, guard: &GuardSleep) -> f64 {
+        guard.hist.iter().cloned().max().unwrap_or(0) as f64
+    }
+}
+
+struct MostNightsAsleep;
+
+impl Strategy for MostNightsAsleep {
+    fn name(&self) -> &'static str {
+        "most nights asleep"
+    }
+
+    fn score(&self, guard: &GuardSleep) -> f64 {
+        guard.nights() as f64
+    }
+}
+
+struct LongestNap;
+
+impl Strategy for LongestNap {
+    fn name(&self) -> &'static str {
+        "longest single nap"
+    }
+
+    fn score(&self, guard: &GuardSleep) -> f64 {
+        guard.longest_nap() as f64
+    }
+}
+
+// The guard whose naps are bunched most tightly around the same time.
+struct MostConsistentMinute;
+
+impl Strategy for MostConsistentMinute {
+    fn name(&self) -> &'static str {
+        "most consistent minute"
+    }
+
+    fn score(&self, guard: &GuardSleep) -> f64 {
+        -guard.minute_spread()
     }
+}
+
+#[derive(Debug, PartialEq)]
+struct Selection {
+    id: i64,
+    // Tied minutes are all reported, in order.
+    minutes: Vec<i64>,
+    score: f64,
+    total_minutes: i64,
+    // How many times the guard was asleep on the selected minute(s).
+    minute_count: i64,
+    nights: usize,
+    longest_nap: i64,
+    minute_spread: f64,
+}
+
+// Returns every guard tied for the best score under `strategy`, ordered by ID.
+fn select_guards(guards: &BTreeMap<i64, GuardSleep>, strategy: &dyn Strategy) -> Vec<Selection> {
+    let scores: Vec<(&GuardSleep, f64)> = guards.values().map(|g| (g, strategy.score(g))).collect();
+    let best = scores.iter().map(|&(_, s)| s).fold(f64::NEG_INFINITY, f64::max);
 
-    (id, max_min as i64)
+    scores
+        .into_iter()
+        .filter(|&(_, s)| s == best)
+        .map(|(g, score)| {
+            let minutes = g.most_slept_minutes();
+            Selection {
+                id: g.id,
+                minute_count: g.hist[minutes[0] as usize],
+                minutes,
+                score,
+                total_minutes: g.total,
+                nights: g.nights(),
+                longest_nap: g.longest_nap(),
+                minute_spread: g.minute_spread(),
+            }
+        })
+        .collect()
+}
+
+// Picks the lowest guard ID and minute when there are ties.  Returns `None` if nobody
+// slept at all.
+fn first_choice(selections: &[Selection]) -> Option<(i64, i64)> {
+    selections.first().map(|s| (s.id, s.minutes[0]))
+}
+
+#[cfg(test)]
+fn find_most_slept_min(recs: &mut [Record]) -> Option<(i64, i64)> {
+    let guards = guard_sleep(&calc_spans(recs));
+    first_choice(&select_guards(&guards, &MostMinutesAsleep))
 }
 
 // Naming Fail
-fn find_most_slept_min_pt2(recs: &mut Vec<Record>) -> (i64, i64) {
-    let spans = calc_spans(recs);
-    let hists = get_sleep_histograms(&spans);
+#[cfg(test)]
+fn find_most_slept_min_pt2(recs: &mut [Record]) -> Option<(i64, i64)> {
+    let guards = guard_sleep(&calc_spans(recs));
+    first_choice(&select_guards(&guards, &MostFrequentMinute))
+}
 
-    let mut sleepiest_id = None;
-    let mut max_min = 0;
-    let mut max_overlap = 0;
+struct GuardStats {
+    id: i64,
+    shifts: usize,
+    shifts_with_sleep: usize,
+    naps: usize,
+    total_minutes: i64,
+    longest_nap: i64,
+    // The earliest minute the guard was asleep on most often, and how many shifts that was.
+    likely_minute: Option<i64>,
+    likely_minute_count: i64,
+}
+
+impl GuardStats {
+    fn mean_minutes(&self) -> f64 {
+        self.total_minutes as f64 / self.shifts as f64
+    }
+
+    // Chance the guard is asleep on `likely_minute` on any given shift.
+    fn likely_minute_probability(&self) -> f64 {
+        self.likely_minute_count as f64 / self.shifts as f64
+    }
+
+    fn sleep_fraction(&self) -> f64 {
+        self.shifts_with_sleep as f64 / self.shifts as f64
+    }
+}
+
+// Stats for every guard that worked a shift, most minutes asleep first.
+fn guard_stats(recs: &mut [Record], unfinished: UnfinishedNap) -> Vec<GuardStats> {
+    let shifts = calc_shifts(recs);
+    let spans = calc_spans_with(recs, unfinished);
+
+    let mut stats: BTreeMap<i64, GuardStats> = BTreeMap::new();
+    for shift in &shifts {
+        let entry = stats.entry(shift.id).or_insert_with(|| GuardStats {
+            id: shift.id,
+            shifts: 0,
+            shifts_with_sleep: 0,
+            naps: 0,
+            total_minutes: 0,
+            longest_nap: 0,
+            likely_minute: None,
+            likely_minute_count: 0,
+        });
+        entry.shifts += 1;
+    }
+
+    // Naps can't outlast the shift they started in, so each belongs to the latest shift
+    // starting at or before it.
+    let mut slept_in = vec![false; shifts.len()];
+    let mut minutes_asleep = vec![[false; 60]; shifts.len()];
+    for span in &spans {
+        let shift = shifts.partition_point(|s| s.start <= span.start) - 1;
+        slept_in[shift] = true;
+
+        let mut t = span.start;
+        while t < span.end {
+            minutes_asleep[shift][t.minute() as usize] = true;
+            t += Duration::minutes(1);
+        }
+
+        let len = span.end.signed_duration_since(span.start).num_minutes();
+        let entry = stats.get_mut(&span.id).unwrap();
+        entry.naps += 1;
+        entry.total_minutes += len;
+        entry.longest_nap = cmp::max(entry.longest_nap, len);
+    }
+    for (shift, _) in shifts.iter().zip(slept_in).filter(|&(_, slept)| slept) {
+        stats.get_mut(&shift.id).unwrap().shifts_with_sleep += 1;
+    }
+
+    // A nap running past the hour covers some minutes twice, but still only counts once
+    // towards how many shifts the guard was asleep on them.
+    let mut hists: BTreeMap<i64, [i64; 60]> = BTreeMap::new();
+    for (shift, minutes) in shifts.iter().zip(&minutes_asleep) {
+        let hist = hists.entry(shift.id).or_insert([0; 60]);
+        for (count, _) in hist.iter_mut().zip(minutes).filter(|&(_, &asleep)| asleep) {
+            *count += 1;
+        }
+    }
     for (id, hist) in hists {
-        for m in 0..hist.len() {
-            let overlap = hist[m];
+        let count = hist.iter().cloned().max().unwrap();
+        if count > 0 {
+            let entry = stats.get_mut(&id).unwrap();
+            entry.likely_minute = hist.iter().position(|&c| c == count).map(|m| m as i64);
+            entry.likely_minute_count = count;
+        }
+    }
+
+    let mut stats: Vec<GuardStats> = stats.into_values().collect();
+    stats.sort_by(|a, b| b.total_minutes.cmp(&a.total_minutes).then(a.id.cmp(&b.id)));
+    stats
+}
+
+fn render_stats_table(stats: &[GuardStats]) -> String {
+    let mut out = format!(
+        "{:>6} {:>6} {:>5} {:>6} {:>7} {:>7} {:>6} {:>6} {:>6}\n",
+        "guard", "shifts", "naps", "total", "mean", "longest", "minute", "prob", "slept"
+    );
+    for s in stats {
+        let minute = s.likely_minute.map_or("-".to_string(), |m| m.to_string());
+        out.push_str(&format!(
+            "{:>6} {:>6} {:>5} {:>6} {:>7.2} {:>7} {:>6} {:>6.2} {:>6.2}\n",
+            s.id,
+            s.shifts,
+            s.naps,
+            s.total_minutes,
+            s.mean_minutes(),
+            s.longest_nap,
+            minute,
+            s.likely_minute_probability(),
+            s.sleep_fraction()
+        ));
+    }
+    out
+}
+
+fn render_stats_json(stats: &[GuardStats]) -> String {
+    let rows: Vec<String> = stats
+        .iter()
+        .map(|s| {
+            let minute = s.likely_minute.map_or("null".to_string(), |m| m.to_string());
+            format!(
+                concat!(
+                    "  {{\"guard\": {}, \"shifts\": {}, \"naps\": {}, \"total_minutes\": {}, ",
+                    "\"mean_minutes\": {}, \"longest_nap\": {}, \"likely_minute\": {}, ",
+                    "\"likely_minute_probability\": {}, \"sleep_fraction\": {}}}"
+                ),
+                s.id,
+                s.shifts,
+                s.naps,
+                s.total_minutes,
+                s.mean_minutes(),
+                s.longest_nap,
+                minute,
+                s.likely_minute_probability(),
+                s.sleep_fraction()
+            )
+        })
+        .collect();
+
+    if rows.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n{}\n]\n", rows.join(",\n"))
+}
+
+// Records are kept in the order they'd be sorted into, with ties broken by arrival order.
+type LogKey = (DateTime, usize);
+
+// A guard log that takes records one at a time, in any order, and keeps each guard's
+// sleep up to date as they arrive.  A record can only change the naps of the shift it
+// lands in (and, for a new shift, the one it cuts short), so only those get recomputed.
+struct GuardLog {
+    recs: BTreeMap<LogKey, Action>,
+    // The naps in each shift, keyed by the shift's begin record.
+    shifts: BTreeMap<LogKey, Vec<Span>>,
+    guards: BTreeMap<i64, GuardSleep>,
+    next_seq: usize,
+    unfinished: UnfinishedNap,
+}
+
+impl GuardLog {
+    fn new(unfinished: UnfinishedNap) -> GuardLog {
+        GuardLog {
+            recs: BTreeMap::new(),
+            shifts: BTreeMap::new(),
+            guards: BTreeMap::new(),
+            next_seq: 0,
+            unfinished,
+        }
+    }
+
+    fn insert(&mut self, rec: Record) {
+        let key = (rec.time, self.next_seq);
+        self.next_seq += 1;
+        self.recs.insert(key, rec.action);
+
+        let prev = self.shifts.range(..key).next_back().map(|(&k, _)| k);
+        if let Action::BeginShift { .. } = rec.action {
+            self.shifts.insert(key, Vec::new());
+            self.refresh(key);
+        }
+        if let Some(prev) = prev {
+            self.refresh(prev);
+        }
+    }
+
+    fn refresh(&mut self, shift: LogKey) {
+        for span in self.shifts.remove(&shift).unwrap_or_default() {
+            self.apply(&span, -1);
+        }
 
-            if overlap > max_overlap {
-                max_min = m;
-                max_overlap = overlap;
-                sleepiest_id = Some(id);
+        // The next shift's begin record comes along so that naps can end at the shift change.
+        let next = self.shifts.range((Excluded(shift), Unbounded)).next().map(|(&k, _)| k);
+        let end = next.map_or(Unbounded, Included);
+        let mut recs: Vec<Record> = self
+            .recs
+            .range((Included(shift), end))
+            .map(|(&(time, _), &action)| Record { time, action })
+            .collect();
+
+        let spans = calc_spans_with(&mut recs, self.unfinished);
+        for span in &spans {
+            self.apply(span, 1);
+        }
+        self.shifts.insert(shift, spans);
+    }
+
+    // Adds (or with a `sign` of -1, takes away) a nap from its guard's sleep.
+    fn apply(&mut self, span: &Span, sign: i64) {
+        let guard = self.guards.entry(span.id).or_insert_with(|| GuardSleep {
+            id: span.id,
+            hist: [0; 60],
+            total: 0,
+            naps: Vec::new(),
+        });
+
+        let mut t = span.start;
+        while t < span.end {
+            guard.hist[t.minute() as usize] += sign;
+            t += Duration::minutes(1);
+        }
+        guard.total += sign * span.end.signed_duration_since(span.start).num_minutes();
+
+        if sign > 0 {
+            let pos = guard.naps.partition_point(|n| n.start <= span.start);
+            guard.naps.insert(pos, span.clone());
+        } else {
+            let pos = guard.naps.iter().position(|n| n == span).unwrap();
+            guard.naps.remove(pos);
+            if guard.naps.is_empty() {
+                self.guards.remove(&span.id);
             }
         }
     }
 
-    (sleepiest_id.unwrap(), max_min as i64)
+    #[cfg(test)]
+    fn spans(&self) -> Vec<Span> {
+        self.shifts.values().flatten().cloned().collect()
+    }
+
+    fn select(&self, strategy: &dyn Strategy) -> Vec<Selection> {
+        select_guards(&self.guards, strategy)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum AnomalyKind {
+    // Logged before any guard began a shift.
+    NoGuard,
+    WakeWithoutSleep,
+    // Fell asleep again without waking up; the earlier nap is lost.
+    DoubleAsleep,
+    // The guard was still asleep when the next shift began or the log ended.
+    StillAsleep,
+    DuplicateTimestamp { first_line: usize },
+    ShiftWithoutSleep,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Anomaly {
+    line: usize,
+    record: String,
+    kind: AnomalyKind,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            AnomalyKind::NoGuard => "no guard is on duty".to_string(),
+            AnomalyKind::WakeWithoutSleep => "wakes up without falling asleep".to_string(),
+            AnomalyKind::DoubleAsleep => "falls asleep while already asleep".to_string(),
+            AnomalyKind::StillAsleep => "still asleep when the shift ends".to_string(),
+            AnomalyKind::DuplicateTimestamp { first_line } => {
+                format!("same time as line {}", first_line)
+            }
+            AnomalyKind::ShiftWithoutSleep => "shift without any sleep".to_string(),
+        };
+        write!(f, "line {}: {}: {}", self.line, what, self.record)
+    }
+}
+
+// Checks the records, given in input order with one record per line, for sequences that
+// `calc_spans` can't make sense of or that are worth a second look.  The records are
+// replayed in time order, the same way `calc_spans` sees them.
+fn validate_records(recs: &[Record]) -> Vec<Anomaly> {
+    let mut order: Vec<usize> = (0..recs.len()).collect();
+    order.sort_by_key(|&i| recs[i].time);
+
+    let mut anomalies = Vec::new();
+    let mut report =
+        |i: usize, kind| anomalies.push(Anomaly { line: i + 1, record: recs[i].to_string(), kind });
+
+    // Indices of the current shift's record, whether it has slept, and the current nap.
+    let mut shift: Option<(usize, bool)> = None;
+    let mut asleep: Option<usize> = None;
+    // The first record logged at the current time.
+    let mut first: Option<usize> = None;
+
+    for &i in &order {
+        match first {
+            Some(f) if recs[f].time == recs[i].time => {
+                report(i, AnomalyKind::DuplicateTimestamp { first_line: f + 1 })
+            }
+            _ => first = Some(i),
+        }
+
+        match recs[i].action {
+            Action::BeginShift { .. } => {
+                if let Some(a) = asleep.take() {
+                    report(a, AnomalyKind::StillAsleep);
+                }
+                if let Some((s, false)) = shift {
+                    report(s, AnomalyKind::ShiftWithoutSleep);
+                }
+                shift = Some((i, false));
+            }
+            Action::Asleep => match shift {
+                None => report(i, AnomalyKind::NoGuard),
+                Some((_, ref mut slept)) => {
+                    if asleep.is_some() {
+                        report(i, AnomalyKind::DoubleAsleep);
+                    }
+                    *slept = true;
+                    asleep = Some(i);
+                }
+            },
+            Action::Awake => {
+                if shift.is_none() {
+                    report(i, AnomalyKind::NoGuard);
+                } else if asleep.is_none() {
+                    report(i, AnomalyKind::WakeWithoutSleep);
+                }
+                asleep = None;
+            }
+        }
+    }
+    if let Some(a) = asleep {
+        report(a, AnomalyKind::StillAsleep);
+    }
+    if let Some((s, false)) = shift {
+        report(s, AnomalyKind::ShiftWithoutSleep);
+    }
+
+    anomalies.sort_by_key(|a| a.line);
+    anomalies
+}
+
+const CSV_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+fn write_spans_csv<W: Write>(w: &mut W, spans: &[Span]) -> Result<(), Error> {
+    writeln!(w, "guard,start,end,minutes")?;
+    for s in spans {
+        writeln!(
+            w,
+            "{},{},{},{}",
+            s.id,
+            s.start.format(CSV_TIME_FORMAT),
+            s.end.format(CSV_TIME_FORMAT),
+            s.end.signed_duration_since(s.start).num_minutes()
+        )?;
+    }
+    Ok(())
+}
+
+// One row per guard, with a column for each minute of the midnight hour.
+fn write_histograms_csv<W: Write>(w: &mut W, hists: &HashMap<i64, [i64; 60]>) -> Result<(), Error> {
+    let minutes: Vec<String> = (0..60).map(|m| m.to_string()).collect();
+    writeln!(w, "guard,{}", minutes.join(","))?;
+
+    let mut ids: Vec<&i64> = hists.keys().collect();
+    ids.sort();
+    for id in ids {
+        let counts: Vec<String> = hists[id].iter().map(|c| c.to_string()).collect();
+        writeln!(w, "{},{}", id, counts.join(","))?;
+    }
+    Ok(())
+}
+
+fn parse_span_csv(line: &str) -> Result<Span, Error> {
+    let invalid =
+        |msg: &str| Error::new(ErrorKind::InvalidData, format!("{} in \"{}\"", msg, line));
+
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    if fields.len() != 3 && fields.len() != 4 {
+        return Err(invalid("Expected guard,start,end[,minutes]"));
+    }
+
+    let id = fields[0].parse().map_err(|_| invalid("Bad guard ID"))?;
+    let start = parse_time(fields[1])?;
+    let end = parse_time(fields[2])?;
+    if end <= start {
+        return Err(invalid("Span doesn't end after it starts"));
+    }
+    // The duration column is only there for people reading the file, but it had better agree.
+    if fields.len() == 4 {
+        let minutes: i64 = fields[3].parse().map_err(|_| invalid("Bad duration"))?;
+        if minutes != end.signed_duration_since(start).num_minutes() {
+            return Err(invalid("Duration doesn't match start and end"));
+        }
+    }
+
+    Ok(Span { id, start, end })
+}
+
+// Reads spans written by `write_spans_csv`, or anything else with the same columns.  The
+// header line and blank lines are skipped.
+fn read_spans_csv<R: Read>(io: R) -> Result<Vec<Span>, Error> {
+    let mut spans = Vec::new();
+    for (i, line) in BufReader::new(io).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || (i == 0 && line.starts_with("guard")) {
+            continue;
+        }
+        let span = parse_span_csv(&line)
+            .map_err(|e| Error::new(e.kind(), format!("line {}: {}", i + 1, e)))?;
+        spans.push(span);
+    }
+    Ok(spans)
 }
 
 fn read<R: Read>(io: R) -> Result<Vec<Record>, Error> {
@@ -210,10 +1053,114 @@ fn read<R: Read>(io: R) -> Result<Vec<Record>, Error> {
 
 fn main() -> Result<(), Error> {
     let mut input = read(File::open("input.txt")?)?;
-    let res = find_most_slept_min(&mut input);
-    println!("Pt 1 answer: {}", res.0 * res.1);
-    let res2 = find_most_slept_min_pt2(&mut input);
-    println!("Pt 2 answer: {}", res2.0 * res2.1);
+
+    // `--unfinished=<drop|shift-change|midnight-hour>` picks what happens to naps that are
+    // still going when the shift changes or the log ends.  They're dropped by default.
+    let mut unfinished = UnfinishedNap::Drop;
+    let mut args: Vec<String> = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--unfinished=") {
+            Some(policy) => unfinished = parse_unfinished(policy)?,
+            None => args.push(arg),
+        }
+    }
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["--report"] => {
+            print!("{}", render_stats_table(&guard_stats(&mut input, unfinished)));
+            return Ok(());
+        }
+        ["--json"] => {
+            print!("{}", render_stats_json(&guard_stats(&mut input, unfinished)));
+            return Ok(());
+        }
+        ["--chart", options @ ..] => {
+            let (filter, order) = parse_chart_args(options)?;
+            print!("{}", render_chart(&calc_spans_with(&mut input, unfinished), &filter, order));
+            return Ok(());
+        }
+        ["--at", time] => {
+            let t = parse_time(time)?;
+            let index = SleepIndex::new(&mut input, unfinished);
+            match index.on_duty_at(t) {
+                Some(id) => println!("On duty: guard #{}", id),
+                None => println!("On duty: nobody"),
+            }
+            println!("Asleep: {:?}", index.asleep_at(t));
+            return Ok(());
+        }
+        ["--max-concurrent"] => {
+            let (max, periods) = SleepIndex::new(&mut input, unfinished).max_concurrent_asleep();
+            println!("At most {} guard(s) asleep at once, during:", max);
+            for (start, end) in periods {
+                println!("  {} - {}", start.format("%Y-%m-%d %H:%M"), end.format("%Y-%m-%d %H:%M"));
+            }
+            return Ok(());
+        }
+        ["--validate"] => {
+            for anomaly in validate_records(&input) {
+                println!("{}", anomaly);
+            }
+            return Ok(());
+        }
+        ["--export-spans", path] => {
+            return write_spans_csv(
+                &mut File::create(path)?,
+                &calc_spans_with(&mut input, unfinished),
+            );
+        }
+        ["--export-histograms", path] => {
+            let hists = get_sleep_histograms(&calc_spans_with(&mut input, unfinished));
+            return write_histograms_csv(&mut File::create(path)?, &hists);
+        }
+        ["--spans", path] => {
+            let guards = guard_sleep(&read_spans_csv(File::open(path)?)?);
+            let strategies: [&dyn Strategy; 5] = [
+                &MostMinutesAsleep,
+                &MostFrequentMinute,
+                &MostNightsAsleep,
+                &LongestNap,
+                &MostConsistentMinute,
+            ];
+            for strategy in strategies.iter() {
+                for sel in select_guards(&guards, *strategy) {
+                    println!("{}: guard #{} at minute {}", strategy.name(), sel.id, sel.minutes[0]);
+                }
+            }
+            return Ok(());
+        }
+        [] => (),
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unrecognized option \"{}\"", other.join(" ")),
+            ))
+        }
+    }
+
+    let mut log = GuardLog::new(unfinished);
+    for rec in input {
+        log.insert(rec);
+    }
+
+    match first_choice(&log.select(&MostMinutesAsleep)) {
+        Some((id, minute)) => println!("Pt 1 answer: {}", id * minute),
+        None => println!("Pt 1 answer: no guard slept"),
+    }
+    match first_choice(&log.select(&MostFrequentMinute)) {
+        Some((id, minute)) => println!("Pt 2 answer: {}", id * minute),
+        None => println!("Pt 2 answer: no guard slept"),
+    }
+
+    let others: [&dyn Strategy; 3] = [&MostNightsAsleep, &LongestNap, &MostConsistentMinute];
+    for strategy in others.iter() {
+        match first_choice(&log.select(*strategy)) {
+            Some((id, minute)) => {
+                println!("{}: guard #{} at minute {}", strategy.name(), id, minute)
+            }
+            None => println!("{}: no guard slept", strategy.name()),
+        }
+    }
 
     Ok(())
 }
@@ -222,20 +1169,15 @@ fn main() -> Result<(), Error> {
 mod test {
     use super::*;
 
+    fn dt(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime {
+        chrono::NaiveDate::from_ymd_opt(y, mo, d).unwrap().and_hms_opt(h, mi, 0).unwrap()
+    }
+
     #[test]
     fn parse_time_test() {
-        assert_eq!(
-            chrono::NaiveDate::from_ymd(1518, 11, 1).and_hms(0, 0, 0),
-            parse_time("1518-11-01 00:00").unwrap()
-        );
-        assert_eq!(
-            chrono::NaiveDate::from_ymd(1518, 11, 1).and_hms(0, 5, 0),
-            parse_time("1518-11-01 00:05").unwrap()
-        );
-        assert_eq!(
-            chrono::NaiveDate::from_ymd(1518, 11, 1).and_hms(23, 58, 0),
-            parse_time("1518-11-01 23:58").unwrap()
-        );
+        assert_eq!(dt(1518, 11, 1, 0, 0), parse_time("1518-11-01 00:00").unwrap());
+        assert_eq!(dt(1518, 11, 1, 0, 5), parse_time("1518-11-01 00:05").unwrap());
+        assert_eq!(dt(1518, 11, 1, 23, 58), parse_time("1518-11-01 23:58").unwrap());
     }
 
     #[test]
@@ -248,10 +1190,7 @@ mod test {
     #[test]
     fn parse_record_test() {
         assert_eq!(
-            Record {
-                time: chrono::NaiveDate::from_ymd(1518, 11, 1).and_hms(0, 0, 0),
-                action: Action::BeginShift { id: 10 }
-            },
+            Record { time: dt(1518, 11, 1, 0, 0), action: Action::BeginShift { id: 10 } },
             parse_record("[1518-11-01 00:00] Guard #10 begins shift").unwrap()
         );
     }
@@ -290,12 +1229,509 @@ mod test {
     #[test]
     fn find_most_slept_min_test() {
         let mut recs = get_recs();
-        assert_eq!((10, 24), find_most_slept_min(&mut recs));
+        assert_eq!(Some((10, 24)), find_most_slept_min(&mut recs));
     }
 
     #[test]
     fn find_most_slept_min_pt_2test() {
         let mut recs = get_recs();
-        assert_eq!((99, 45), find_most_slept_min_pt2(&mut recs));
+        assert_eq!(Some((99, 45)), find_most_slept_min_pt2(&mut recs));
+    }
+
+    #[test]
+    fn histogram_across_boundaries_test() {
+        let spans = vec![
+            Span { id: 1, start: dt(1518, 11, 1, 23, 58), end: dt(1518, 11, 2, 0, 10) },
+            Span { id: 2, start: dt(1518, 11, 3, 0, 30), end: dt(1518, 11, 3, 1, 40) },
+        ];
+        let hists = get_sleep_histograms(&spans);
+
+        let mut expected = [0; 60];
+        for m in (58..60).chain(0..10) {
+            expected[m] = 1;
+        }
+        assert_eq!(expected[..], hists[&1][..]);
+
+        let mut expected = [1; 60];
+        for e in &mut expected[30..40] {
+            *e = 2;
+        }
+        assert_eq!(expected[..], hists[&2][..]);
+
+        let totals = calc_sleep_min(&spans);
+        assert_eq!(12, totals[&1]);
+        assert_eq!(70, totals[&2]);
+    }
+
+    fn get_unfinished_recs() -> Vec<Record> {
+        vec![
+            parse_record("[1518-11-01 00:00] Guard #10 begins shift").unwrap(),
+            parse_record("[1518-11-01 00:40] falls asleep").unwrap(),
+            parse_record("[1518-11-01 23:58] Guard #99 begins shift").unwrap(),
+            parse_record("[1518-11-02 00:20] wakes up").unwrap(),
+            parse_record("[1518-11-03 00:01] Guard #10 begins shift").unwrap(),
+            parse_record("[1518-11-03 00:50] falls asleep").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn unfinished_nap_test() {
+        // Guard #99 never fell asleep, so the wake up doesn't give them a nap either way.
+        let mut recs = get_unfinished_recs();
+        assert!(calc_spans_with(&mut recs, UnfinishedNap::Drop).is_empty());
+
+        let spans = calc_spans_with(&mut recs, UnfinishedNap::EndAtShiftChange);
+        assert_eq!(1, spans.len());
+        assert_eq!(
+            (10, dt(1518, 11, 1, 0, 40), dt(1518, 11, 1, 23, 58)),
+            (spans[0].id, spans[0].start, spans[0].end)
+        );
+
+        let spans = calc_spans_with(&mut recs, UnfinishedNap::EndOfMidnightHour);
+        let spans: Vec<_> = spans.iter().map(|s| (s.id, s.start, s.end)).collect();
+        assert_eq!(
+            vec![
+                (10, dt(1518, 11, 1, 0, 40), dt(1518, 11, 1, 1, 0)),
+                (10, dt(1518, 11, 3, 0, 50), dt(1518, 11, 3, 1, 0)),
+            ],
+            spans
+        );
+
+        assert_eq!(dt(1518, 11, 2, 1, 0), end_of_midnight_hour(dt(1518, 11, 1, 23, 50)));
+        assert_eq!(dt(1518, 11, 2, 1, 0), end_of_midnight_hour(dt(1518, 11, 1, 1, 0)));
+    }
+
+    #[test]
+    fn validate_records_test() {
+        let recs = vec![
+            parse_record("[1518-11-01 00:05] falls asleep").unwrap(),
+            parse_record("[1518-11-01 00:00] Guard #10 begins shift").unwrap(),
+            parse_record("[1518-10-31 23:55] wakes up").unwrap(),
+            parse_record("[1518-11-01 00:25] wakes up").unwrap(),
+            parse_record("[1518-11-01 00:26] wakes up").unwrap(),
+            parse_record("[1518-11-01 23:58] Guard #99 begins shift").unwrap(),
+            parse_record("[1518-11-02 00:40] falls asleep").unwrap(),
+            parse_record("[1518-11-02 00:45] falls asleep").unwrap(),
+            parse_record("[1518-11-02 00:50] wakes up").unwrap(),
+            parse_record("[1518-11-03 00:05] Guard #10 begins shift").unwrap(),
+            parse_record("[1518-11-04 00:02] Guard #99 begins shift").unwrap(),
+            parse_record("[1518-11-04 00:36] falls asleep").unwrap(),
+            parse_record("[1518-11-04 00:36] wakes up").unwrap(),
+            parse_record("[1518-11-05 00:03] Guard #99 begins shift").unwrap(),
+            parse_record("[1518-11-05 00:45] falls asleep").unwrap(),
+        ];
+
+        let found: Vec<(usize, AnomalyKind)> =
+            validate_records(&recs).into_iter().map(|a| (a.line, a.kind)).collect();
+        assert_eq!(
+            vec![
+                (3, AnomalyKind::NoGuard),
+                (5, AnomalyKind::WakeWithoutSleep),
+                (8, AnomalyKind::DoubleAsleep),
+                (10, AnomalyKind::ShiftWithoutSleep),
+                (13, AnomalyKind::DuplicateTimestamp { first_line: 12 }),
+                (15, AnomalyKind::StillAsleep),
+            ],
+            found
+        );
+
+        assert_eq!(
+            "line 5: wakes up without falling asleep: [1518-11-01 00:26] wakes up",
+            validate_records(&recs)[1].to_string()
+        );
+
+        assert!(validate_records(&get_recs()).is_empty());
+
+        let recs = vec![
+            parse_record("[1518-11-01 00:00] falls asleep").unwrap(),
+            parse_record("[1518-11-01 00:10] Guard #1 begins shift").unwrap(),
+            parse_record("[1518-11-01 00:20] falls asleep").unwrap(),
+            parse_record("[1518-11-01 00:20] wakes up").unwrap(),
+            parse_record("[1518-11-01 00:20] wakes up").unwrap(),
+        ];
+        let found: Vec<(usize, AnomalyKind)> =
+            validate_records(&recs).into_iter().map(|a| (a.line, a.kind)).collect();
+        assert_eq!(
+            vec![
+                (1, AnomalyKind::NoGuard),
+                (4, AnomalyKind::DuplicateTimestamp { first_line: 3 }),
+                (5, AnomalyKind::DuplicateTimestamp { first_line: 3 }),
+                (5, AnomalyKind::WakeWithoutSleep),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn render_chart_test() {
+        let mut recs = get_recs();
+        let spans = calc_spans(&mut recs);
+
+        assert_eq!(
+            concat!(
+                "Date   ID   Minute\n",
+                "            000000000011111111112222222222333333333344444444445555555555\n",
+                "            012345678901234567890123456789012345678901234567890123456789\n",
+                "11-01  #10  .....####################.....#########################.....\n",
+                "11-02  #99  ........................................##########..........\n",
+                "11-03  #10  ........................#####...............................\n",
+                "11-04  #99  ....................................##########..............\n",
+                "11-05  #99  .............................................##########.....\n",
+            ),
+            render_chart(&spans, &ChartFilter::default(), ChartOrder::ByDate)
+        );
+
+        let filter = ChartFilter {
+            guard: None,
+            from: NaiveDate::from_ymd_opt(1518, 11, 2),
+            to: NaiveDate::from_ymd_opt(1518, 11, 4),
+        };
+        let chart = render_chart(&spans, &filter, ChartOrder::ByGuard);
+        let rows: Vec<&str> = chart.lines().skip(3).map(|l| &l[..10]).collect();
+        assert_eq!(vec!["11-03  #10", "11-02  #99", "11-04  #99"], rows);
+
+        let filter = ChartFilter { guard: Some(10), ..Default::default() };
+        let chart = render_chart(&spans, &filter, ChartOrder::ByDate);
+        let rows: Vec<&str> = chart.lines().skip(3).map(|l| &l[..10]).collect();
+        assert_eq!(vec!["11-01  #10", "11-03  #10"], rows);
+
+        // Naps starting before midnight still only show the midnight hour.
+        let spans =
+            vec![Span { id: 1321, start: dt(1518, 3, 4, 23, 58), end: dt(1518, 3, 5, 0, 3) }];
+        let chart = render_chart(&spans, &ChartFilter::default(), ChartOrder::ByDate);
+        assert_eq!(
+            "03-05  #1321  ###.........................................................",
+            chart.lines().nth(3).unwrap()
+        );
+
+        let (filter, order) =
+            parse_chart_args(&["--guard", "99", "--by-guard", "--from", "1518-11-02"]).unwrap();
+        assert_eq!(ChartOrder::ByGuard, order);
+        assert_eq!(
+            (Some(99), NaiveDate::from_ymd_opt(1518, 11, 2), None),
+            (filter.guard, filter.from, filter.to)
+        );
+        assert!(parse_chart_args(&["--guard"]).is_err());
+        assert!(parse_chart_args(&["--to", "11-02"]).is_err());
+        assert!(parse_chart_args(&["--sideways"]).is_err());
+    }
+
+    #[test]
+    fn calc_shifts_test() {
+        let mut recs = get_recs();
+        let shifts: Vec<_> =
+            calc_shifts(&mut recs).iter().map(|s| (s.id, s.start, s.end)).collect();
+        assert_eq!(
+            vec![
+                (10, dt(1518, 11, 1, 0, 0), dt(1518, 11, 1, 23, 58)),
+                (99, dt(1518, 11, 1, 23, 58), dt(1518, 11, 3, 0, 5)),
+                (10, dt(1518, 11, 3, 0, 5), dt(1518, 11, 4, 0, 2)),
+                (99, dt(1518, 11, 4, 0, 2), dt(1518, 11, 5, 0, 3)),
+                (99, dt(1518, 11, 5, 0, 3), DateTime::MAX),
+            ],
+            shifts
+        );
+    }
+
+    #[test]
+    fn sleep_index_test() {
+        let mut recs = get_recs();
+        let index = SleepIndex::new(&mut recs, UnfinishedNap::Drop);
+
+        assert_eq!(vec![10], index.asleep_at(dt(1518, 11, 1, 0, 5)));
+        assert_eq!(vec![10], index.asleep_at(dt(1518, 11, 1, 0, 24)));
+        assert!(index.asleep_at(dt(1518, 11, 1, 0, 25)).is_empty());
+        assert!(index.asleep_at(dt(1518, 10, 1, 0, 25)).is_empty());
+        assert_eq!(vec![99], index.asleep_at(dt(1518, 11, 5, 0, 54)));
+
+        assert_eq!(None, index.on_duty_at(dt(1518, 10, 31, 23, 59)));
+        assert_eq!(Some(10), index.on_duty_at(dt(1518, 11, 1, 0, 0)));
+        assert_eq!(Some(10), index.on_duty_at(dt(1518, 11, 1, 23, 57)));
+        assert_eq!(Some(99), index.on_duty_at(dt(1518, 11, 1, 23, 58)));
+        assert_eq!(Some(99), index.on_duty_at(dt(1520, 1, 1, 0, 0)));
+
+        // Guards never overlap in the puzzle, so every nap ties for the most concurrent.
+        let (max, periods) = index.max_concurrent_asleep();
+        assert_eq!(1, max);
+        assert_eq!(6, periods.len());
+    }
+
+    #[test]
+    fn max_concurrent_asleep_test() {
+        let spans = vec![
+            Span { id: 1, start: dt(1518, 11, 1, 0, 0), end: dt(1518, 11, 1, 0, 30) },
+            Span { id: 2, start: dt(1518, 11, 1, 0, 10), end: dt(1518, 11, 1, 0, 20) },
+            Span { id: 3, start: dt(1518, 11, 1, 0, 15), end: dt(1518, 11, 1, 0, 40) },
+            Span { id: 4, start: dt(1518, 11, 1, 0, 20), end: dt(1518, 11, 1, 0, 25) },
+            Span { id: 5, start: dt(1518, 11, 1, 0, 40), end: dt(1518, 11, 1, 0, 45) },
+        ];
+        let index = SleepIndex { naps: IntervalList::new(&spans), shifts: IntervalList::new(&[]) };
+
+        assert_eq!(vec![1, 2, 3], index.asleep_at(dt(1518, 11, 1, 0, 15)));
+        assert_eq!(vec![1, 3, 4], index.asleep_at(dt(1518, 11, 1, 0, 20)));
+        assert_eq!(vec![5], index.asleep_at(dt(1518, 11, 1, 0, 40)));
+        assert_eq!(None, index.on_duty_at(dt(1518, 11, 1, 0, 40)));
+
+        assert_eq!(
+            (3, vec![(dt(1518, 11, 1, 0, 15), dt(1518, 11, 1, 0, 25))]),
+            index.max_concurrent_asleep()
+        );
+        let empty = SleepIndex { naps: IntervalList::new(&[]), shifts: IntervalList::new(&[]) };
+        assert_eq!((0, vec![]), empty.max_concurrent_asleep());
+    }
+
+    #[test]
+    fn strategies_test() {
+        let mut recs = get_recs();
+        let guards = guard_sleep(&calc_spans(&mut recs));
+
+        let pt1 = select_guards(&guards, &MostMinutesAsleep);
+        assert_eq!(1, pt1.len());
+        assert_eq!((10, vec![24], 50.0), (pt1[0].id, pt1[0].minutes.clone(), pt1[0].score));
+        assert_eq!(
+            (50, 2, 2, 25),
+            (pt1[0].total_minutes, pt1[0].minute_count, pt1[0].nights, pt1[0].longest_nap)
+        );
+
+        let pt2 = select_guards(&guards, &MostFrequentMinute);
+        assert_eq!(1, pt2.len());
+        assert_eq!((99, vec![45], 3.0), (pt2[0].id, pt2[0].minutes.clone(), pt2[0].score));
+
+        let nights = select_guards(&guards, &MostNightsAsleep);
+        assert_eq!(vec![99], nights.iter().map(|s| s.id).collect::<Vec<_>>());
+        assert_eq!((3, 30), (nights[0].nights, nights[0].total_minutes));
+
+        let longest = select_guards(&guards, &LongestNap);
+        assert_eq!(vec![10], longest.iter().map(|s| s.id).collect::<Vec<_>>());
+
+        let consistent = select_guards(&guards, &MostConsistentMinute);
+        assert_eq!(vec![99], consistent.iter().map(|s| s.id).collect::<Vec<_>>());
+        assert!(consistent[0].minute_spread < pt1[0].minute_spread);
+    }
+
+    #[test]
+    fn consistent_minute_across_midnight_test() {
+        let mut recs: Vec<Record> = [
+            "[1518-11-01 23:50] Guard #1 begins shift",
+            "[1518-11-01 23:58] falls asleep",
+            "[1518-11-02 00:02] wakes up",
+            "[1518-11-03 00:00] Guard #2 begins shift",
+            "[1518-11-03 00:20] falls asleep",
+            "[1518-11-03 00:30] wakes up",
+        ]
+        .iter()
+        .map(|l| parse_record(l).unwrap())
+        .collect();
+        let guards = guard_sleep(&calc_spans(&mut recs));
+
+        // Minutes 58, 59, 0 and 1 are as close together as 20 to 23.
+        let g1 = &guards[&1];
+        assert_eq!(vec![0, 1, 58, 59], g1.most_slept_minutes());
+        assert!(g1.minute_spread() < guards[&2].minute_spread());
+        let consistent = select_guards(&guards, &MostConsistentMinute);
+        assert_eq!(vec![1], consistent.iter().map(|s| s.id).collect::<Vec<_>>());
+
+        let uniform = GuardSleep { id: 3, hist: [1; 60], total: 60, naps: Vec::new() };
+        assert!(uniform.minute_spread() > 0.999);
+        let mut hist = [0; 60];
+        hist[42] = 5;
+        let single = GuardSleep { id: 3, hist, total: 5, naps: Vec::new() };
+        assert!(single.minute_spread().abs() < 1e-9);
+    }
+
+    #[test]
+    fn strategy_ties_test() {
+        let spans = vec![
+            Span { id: 7, start: dt(1518, 11, 1, 0, 10), end: dt(1518, 11, 1, 0, 12) },
+            Span { id: 3, start: dt(1518, 11, 2, 0, 30), end: dt(1518, 11, 2, 0, 31) },
+            Span { id: 3, start: dt(1518, 11, 3, 0, 40), end: dt(1518, 11, 3, 0, 41) },
+        ];
+        let guards = guard_sleep(&spans);
+
+        let selections = select_guards(&guards, &MostMinutesAsleep);
+        assert_eq!(vec![3, 7], selections.iter().map(|s| s.id).collect::<Vec<_>>());
+        assert_eq!(vec![30, 40], selections[0].minutes);
+        assert_eq!(vec![10, 11], selections[1].minutes);
+        assert_eq!(Some((3, 30)), first_choice(&selections));
+
+        // Nobody asleep means nobody to pick.
+        let guards = guard_sleep(&[]);
+        for strategy in [&MostMinutesAsleep as &dyn Strategy, &MostConsistentMinute].iter() {
+            assert_eq!(None, first_choice(&select_guards(&guards, *strategy)));
+        }
+    }
+
+    #[test]
+    fn guard_stats_test() {
+        let mut recs = get_recs();
+        recs.push(parse_record("[1518-11-06 00:00] Guard #7 begins shift").unwrap());
+        recs.push(parse_record("[1518-11-07 00:01] Guard #10 begins shift").unwrap());
+        let stats = guard_stats(&mut recs, UnfinishedNap::Drop);
+
+        assert_eq!(vec![10, 99, 7], stats.iter().map(|s| s.id).collect::<Vec<_>>());
+
+        let g10 = &stats[0];
+        assert_eq!(
+            (3, 2, 3, 50, 25),
+            (g10.shifts, g10.shifts_with_sleep, g10.naps, g10.total_minutes, g10.longest_nap)
+        );
+        assert_eq!((Some(24), 2), (g10.likely_minute, g10.likely_minute_count));
+        assert!((g10.mean_minutes() - 50.0 / 3.0).abs() < 1e-9);
+        assert!((g10.likely_minute_probability() - 2.0 / 3.0).abs() < 1e-9);
+        assert!((g10.sleep_fraction() - 2.0 / 3.0).abs() < 1e-9);
+
+        let g99 = &stats[1];
+        assert_eq!(
+            (3, 3, 3, 30, 10),
+            (g99.shifts, g99.shifts_with_sleep, g99.naps, g99.total_minutes, g99.longest_nap)
+        );
+        assert_eq!((Some(45), 3), (g99.likely_minute, g99.likely_minute_count));
+        assert_eq!(1.0, g99.likely_minute_probability());
+
+        let g7 = &stats[2];
+        assert_eq!(
+            (1, 0, 0, 0, None),
+            (g7.shifts, g7.shifts_with_sleep, g7.naps, g7.total_minutes, g7.likely_minute)
+        );
+        assert_eq!(0.0, g7.sleep_fraction());
+    }
+
+    #[test]
+    fn guard_stats_long_nap_test() {
+        let mut recs = vec![
+            parse_record("[1518-11-08 23:50] Guard #5 begins shift").unwrap(),
+            parse_record("[1518-11-09 00:00] falls asleep").unwrap(),
+            parse_record("[1518-11-09 01:30] wakes up").unwrap(),
+        ];
+        let stats = guard_stats(&mut recs, UnfinishedNap::Drop);
+
+        let g5 = &stats[0];
+        assert_eq!((1, 1, 90, 90), (g5.shifts, g5.naps, g5.total_minutes, g5.longest_nap));
+        assert_eq!((Some(0), 1), (g5.likely_minute, g5.likely_minute_count));
+        assert_eq!(1.0, g5.likely_minute_probability());
+    }
+
+    #[test]
+    fn render_stats_test() {
+        let mut recs = get_recs();
+        let stats = guard_stats(&mut recs, UnfinishedNap::Drop);
+
+        assert_eq!(
+            concat!(
+                " guard shifts  naps  total    mean longest minute   prob  slept\n",
+                "    10      2     3     50   25.00      25     24   1.00   1.00\n",
+                "    99      3     3     30   10.00      10     45   1.00   1.00\n",
+            ),
+            render_stats_table(&stats)
+        );
+
+        let json = render_stats_json(&stats[1..]);
+        assert_eq!(
+            concat!(
+                "[\n  {\"guard\": 99, \"shifts\": 3, \"naps\": 3, \"total_minutes\": 30, ",
+                "\"mean_minutes\": 10, \"longest_nap\": 10, \"likely_minute\": 45, ",
+                "\"likely_minute_probability\": 1, \"sleep_fraction\": 1}\n]\n"
+            ),
+            json
+        );
+        assert_eq!("[]\n", render_stats_json(&[]));
+    }
+
+    #[test]
+    fn guard_log_test() {
+        let policies = [
+            UnfinishedNap::Drop,
+            UnfinishedNap::EndAtShiftChange,
+            UnfinishedNap::EndOfMidnightHour,
+        ];
+        for &unfinished in policies.iter() {
+            let mut log = GuardLog::new(unfinished);
+            let mut seen = Vec::new();
+
+            // Feed the records in a scrambled order, checking against a full recompute
+            // each time.  Naps are left open along the way, so every policy gets used.
+            let mut recs = get_recs();
+            recs.reverse();
+            let order = [3, 9, 0, 16, 12, 5, 1, 14, 7, 10, 2, 15, 8, 4, 13, 6, 11];
+            let mut recs: Vec<Option<Record>> = recs.into_iter().map(Some).collect();
+            for &i in order.iter() {
+                let rec = recs[i].take().unwrap();
+                seen.push(Record { time: rec.time, action: rec.action });
+                log.insert(rec);
+
+                let spans = calc_spans_with(&mut seen, unfinished);
+                assert_eq!(spans, log.spans());
+                assert_eq!(guard_sleep(&spans), log.guards);
+            }
+
+            assert_eq!(Some((10, 24)), first_choice(&log.select(&MostMinutesAsleep)));
+            assert_eq!(Some((99, 45)), first_choice(&log.select(&MostFrequentMinute)));
+        }
+
+        assert_eq!(UnfinishedNap::EndAtShiftChange, parse_unfinished("shift-change").unwrap());
+        assert!(parse_unfinished("never").is_err());
+    }
+
+    #[test]
+    fn guard_log_input_test() {
+        let mut recs = read(File::open("input.txt").unwrap()).unwrap();
+        let spans = calc_spans(&mut recs);
+
+        let mut log = GuardLog::new(UnfinishedNap::Drop);
+        for rec in recs.into_iter().rev() {
+            log.insert(rec);
+        }
+        assert_eq!(spans, log.spans());
+        assert_eq!(guard_sleep(&spans), log.guards);
+    }
+
+    #[test]
+    fn spans_csv_test() {
+        let mut recs = get_recs();
+        let spans = calc_spans(&mut recs);
+
+        let mut out = Vec::new();
+        write_spans_csv(&mut out, &spans).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(
+            vec!["guard,start,end,minutes", "10,1518-11-01 00:05,1518-11-01 00:25,20"],
+            csv.lines().take(2).collect::<Vec<_>>()
+        );
+        assert_eq!(spans, read_spans_csv(csv.as_bytes()).unwrap());
+
+        // No header and no duration column is fine too.
+        let spans = read_spans_csv("7,1518-11-01 00:10,1518-11-01 00:12\n\n".as_bytes()).unwrap();
+        assert_eq!(
+            vec![Span { id: 7, start: dt(1518, 11, 1, 0, 10), end: dt(1518, 11, 1, 0, 12) }],
+            spans
+        );
+
+        for bad in &[
+            "7,1518-11-01 00:10",
+            "x,1518-11-01 00:10,1518-11-01 00:12",
+            "7,1518-11-01 00:10,yesterday",
+            "7,1518-11-01 00:12,1518-11-01 00:10",
+            "7,1518-11-01 00:10,1518-11-01 00:12,3",
+        ] {
+            let err = read_spans_csv(format!("guard,start,end\n{}\n", bad).as_bytes()).unwrap_err();
+            assert!(err.to_string().starts_with("line 2: "), "{}", err);
+        }
+    }
+
+    #[test]
+    fn histograms_csv_test() {
+        let mut recs = get_recs();
+        let hists = get_sleep_histograms(&calc_spans(&mut recs));
+
+        let mut out = Vec::new();
+        write_histograms_csv(&mut out, &hists).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+
+        assert_eq!(3, rows.len());
+        assert_eq!(61, rows[0].len());
+        assert_eq!(("guard", "0", "59"), (rows[0][0], rows[0][1], rows[0][60]));
+        assert_eq!(("10", "2"), (rows[1][0], rows[1][25]));
+        assert_eq!(("99", "3"), (rows[2][0], rows[2][46]));
     }
 }
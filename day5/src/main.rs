@@ -1,75 +1,221 @@
-use std::collections::HashSet;
-use std::collections::VecDeque;
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, Read};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::thread;
 
-fn parse_poly(s: &str) -> VecDeque<char> {
-    let mut poly = VecDeque::new();
-    for c in s.chars() {
-        poly.push_back(c);
+// Units are ASCII letters, so the polymer is kept as raw bytes.
+fn parse_poly(s: &str) -> Vec<u8> {
+    s.bytes().collect()
+}
+
+// Which units annihilate each other.  Every unit has at most one partner it reacts with
+// (possibly itself), and partners have to be mutual.  That keeps the order reactions happen
+// in from mattering, just like with the puzzle's rule.
+trait Rule: Sync {
+    fn partner(&self, c: u8) -> Option<u8>;
+
+    // The name of the unit `c` is one polarity of.  Removing a unit removes both polarities.
+    fn unit(&self, c: u8) -> u8 {
+        self.partner(c).map_or(c, |p| cmp::min(c, p))
     }
-    poly
 }
 
-fn process_poly(poly: &mut VecDeque<char>) -> usize {
-    let mut i = 0;
+// The puzzle's rule: the same letter in opposite cases.
+struct OppositeCase;
 
-    while i < poly.len() - 1 {
-        let c = poly[i];
-        let c1 = poly[i + 1];
-        if (c.is_lowercase() && c.to_uppercase().next().unwrap() == c1)
-            || (c.is_uppercase() && c.to_lowercase().next().unwrap() == c1)
-        {
-            poly.remove(i); // c
-            poly.remove(i); // c1
+impl Rule for OppositeCase {
+    fn partner(&self, c: u8) -> Option<u8> {
+        if c.is_ascii_lowercase() {
+            Some(c.to_ascii_uppercase())
+        } else if c.is_ascii_uppercase() {
+            Some(c.to_ascii_lowercase())
+        } else {
+            None
+        }
+    }
 
-            // The previous character could match it's new neighbor.
-            if i > 0 {
-                i -= 1;
-            }
+    fn unit(&self, c: u8) -> u8 {
+        c.to_ascii_lowercase()
+    }
+}
+
+// Digits that add up to the given total, e.g. 1 and 9 for 10.
+struct DigitsSumTo(u8);
+
+impl Rule for DigitsSumTo {
+    fn partner(&self, c: u8) -> Option<u8> {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        let other = self.0.checked_sub(c - b'0')?;
+        if other <= 9 {
+            Some(b'0' + other)
         } else {
-            i += 1;
+            None
         }
     }
-    poly.len()
 }
 
-fn get_unique_units(poly: &VecDeque<char>) -> HashSet<char> {
-    let mut units = HashSet::new();
+// An explicit list of pairs.
+struct PairTable {
+    partners: Vec<Option<u8>>,
+}
+
+impl PairTable {
+    fn new(pairs: &[(u8, u8)]) -> Result<PairTable, Error> {
+        let mut partners = vec![None; 256];
+        for &(a, b) in pairs {
+            for &(x, y) in &[(a, b), (b, a)] {
+                match partners[x as usize] {
+                    Some(p) if p != y => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "'{}' reacts with both '{}' and '{}'",
+                                x as char, p as char, y as char
+                            ),
+                        ))
+                    }
+                    _ => partners[x as usize] = Some(y),
+                }
+            }
+        }
+        Ok(PairTable { partners })
+    }
 
-    for c in poly {
-        let lower = c.to_lowercase().next().unwrap();
-        units.insert(lower);
+    // Pairs up the units of two alphabets by position, e.g. "([{" with ")]}".
+    fn from_alphabets(a: &str, b: &str) -> Result<PairTable, Error> {
+        if a.len() != b.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Alphabets differ in length"));
+        }
+        let pairs: Vec<(u8, u8)> = a.bytes().zip(b.bytes()).collect();
+        PairTable::new(&pairs)
     }
+}
 
-    units
+impl Rule for PairTable {
+    fn partner(&self, c: u8) -> Option<u8> {
+        self.partners[c as usize]
+    }
 }
 
-fn remove_unit(poly: &VecDeque<char>, r: char) -> VecDeque<char> {
-    let lower = r.to_lowercase().next().unwrap();
-    let upper = r.to_uppercase().next().unwrap();
+// Parses `letters`, `digits:<total>` or `pairs:<alphabet>:<alphabet>`.
+fn parse_rule(s: &str) -> Result<Box<dyn Rule>, Error> {
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+    if s == "letters" {
+        return Ok(Box::new(OppositeCase));
+    }
+    if let Some(total) = s.strip_prefix("digits:") {
+        let total = total.parse().map_err(|e| invalid(format!("Bad digit total: {}", e)))?;
+        return Ok(Box::new(DigitsSumTo(total)));
+    }
+    if let Some((a, b)) = s.strip_prefix("pairs:").and_then(|p| p.split_once(':')) {
+        return Ok(Box::new(PairTable::from_alphabets(a, b)?));
+    }
+    Err(invalid(format!("Unrecognized rule \"{}\"", s)))
+}
 
-    poly.iter().cloned().filter(|c| *c != lower && *c != upper).collect()
+#[cfg(test)]
+fn process_poly(poly: &mut Vec<u8>) -> usize {
+    process_poly_with(poly, &OppositeCase)
 }
 
-fn find_best_removal(poly: &VecDeque<char>) -> (char, usize) {
-    let units = get_unique_units(&poly);
+// Reduces the polymer in place and returns its new length.  The front of `poly` doubles
+// as a stack of the units that have survived so far: each unit either reacts with the top
+// of the stack or gets pushed onto it, so the whole thing takes a single pass.
+fn process_poly_with(poly: &mut Vec<u8>, rule: &dyn Rule) -> usize {
+    let mut top = 0;
 
-    let mut best_unit = None;
-    let mut best_len = poly.len();
-    for c in units {
-        let mut new_poly = remove_unit(&poly, c);
-        process_poly(&mut new_poly);
-        if new_poly.len() < best_len {
-            best_unit = Some(c);
-            best_len = new_poly.len();
+    for i in 0..poly.len() {
+        let c = poly[i];
+        if top > 0 && rule.partner(poly[top - 1]) == Some(c) {
+            top -= 1;
+        } else {
+            poly[top] = c;
+            top += 1;
         }
     }
 
-    (best_unit.unwrap(), best_len)
+    poly.truncate(top);
+    poly.len()
+}
+
+#[cfg(test)]
+fn get_unique_units(poly: &[u8]) -> HashSet<u8> {
+    get_unique_units_with(poly, &OppositeCase)
+}
+
+fn get_unique_units_with(poly: &[u8], rule: &dyn Rule) -> HashSet<u8> {
+    poly.iter().map(|&c| rule.unit(c)).collect()
+}
+
+#[cfg(test)]
+fn remove_unit(poly: &[u8], r: u8) -> Vec<u8> {
+    remove_unit_with(poly, r, &OppositeCase)
+}
+
+fn remove_unit_with(poly: &[u8], r: u8, rule: &dyn Rule) -> Vec<u8> {
+    let r = rule.unit(r);
+    poly.iter().cloned().filter(|&c| rule.unit(c) != r).collect()
+}
+
+// The reduced length of the polymer with each of its units taken out.  Taking a unit out
+// never stops two other units from reacting, so this works from the reduced polymer, which
+// gives the same lengths with far less to chew through.  Units that reacted away entirely
+// leave the reduced polymer as it is.  The rest are split across threads.
+fn removal_lengths(poly: &[u8], rule: &dyn Rule) -> BTreeMap<u8, usize> {
+    let mut reduced = poly.to_vec();
+    process_poly_with(&mut reduced, rule);
+
+    let remaining = get_unique_units_with(&reduced, rule);
+    let mut lengths: BTreeMap<u8, usize> = get_unique_units_with(poly, rule)
+        .difference(&remaining)
+        .map(|&u| (u, reduced.len()))
+        .collect();
+
+    let mut units: Vec<u8> = remaining.into_iter().collect();
+    units.sort();
+    if units.is_empty() {
+        return lengths;
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = units.len().div_ceil(threads);
+    let reduced = &reduced;
+
+    thread::scope(|s| {
+        let handles: Vec<_> = units
+            .chunks(per_thread)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let lengths = chunk.iter().map(|&u| {
+                        (u, process_poly_with(&mut remove_unit_with(reduced, u, rule), rule))
+                    });
+                    lengths.collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        lengths.extend(handles.into_iter().flat_map(|h| h.join().unwrap()));
+    });
+    lengths
 }
 
-fn read<R: Read>(io: R) -> Result<VecDeque<char>, Error> {
+#[cfg(test)]
+fn find_best_removal(poly: &[u8]) -> Option<(char, usize)> {
+    find_best_removal_with(poly, &OppositeCase)
+}
+
+// Ties go to the earliest unit.  Returns `None` for an empty polymer, which has no units
+// to remove.
+fn find_best_removal_with(poly: &[u8], rule: &dyn Rule) -> Option<(char, usize)> {
+    let lengths = removal_lengths(poly, rule);
+    let (&unit, &len) = lengths.iter().min_by_key(|&(_, &len)| len)?;
+    Some((unit as char, len))
+}
+
+fn read<R: Read>(io: R) -> Result<Vec<u8>, Error> {
     let br = BufReader::new(io);
     let mut p = String::new();
     for line in br.lines() {
@@ -80,11 +226,26 @@ fn read<R: Read>(io: R) -> Result<VecDeque<char>, Error> {
 }
 
 fn main() -> Result<(), Error> {
-    let mut input = read(File::open("input.txt")?)?;
-    let input2 = input.clone();
-    println!("Pt 1: {}", process_poly(&mut input));
-    let (unit, len) = find_best_removal(&input2);
-    println!("Pt 2: {}, {}", unit, len);
+    // `day5 [--rule=<letters|digits:<total>|pairs:<alphabet>:<alphabet>>]`
+    let mut rule: Box<dyn Rule> = Box::new(OppositeCase);
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--rule=") {
+            Some(r) => rule = parse_rule(r)?,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unrecognized option \"{}\"", arg),
+                ))
+            }
+        }
+    }
+
+    let input = read(File::open("input.txt")?)?;
+    println!("Pt 1: {}", process_poly_with(&mut input.clone(), rule.as_ref()));
+    match find_best_removal_with(&input, rule.as_ref()) {
+        Some((unit, len)) => println!("Pt 2: {}, {}", unit, len),
+        None => println!("Pt 2: no units to remove"),
+    }
     Ok(())
 }
 
@@ -95,9 +256,10 @@ mod test {
     #[test]
     fn parse_poly_test() {
         assert_eq!(
-            VecDeque::from(vec!(
-                'd', 'a', 'b', 'A', 'c', 'C', 'a', 'C', 'B', 'A', 'c', 'C', 'c', 'a', 'D', 'A'
-            )),
+            vec!(
+                b'd', b'a', b'b', b'A', b'c', b'C', b'a', b'C', b'B', b'A', b'c', b'C', b'c', b'a',
+                b'D', b'A'
+            ),
             parse_poly("dabAcCaCBAcCcaDA")
         );
     }
@@ -107,32 +269,132 @@ mod test {
         let mut poly = parse_poly("dabAcCaCBAcCcaDA");
         let l = process_poly(&mut poly);
         assert_eq!(10, l);
-        assert_eq!(VecDeque::from(vec!('d', 'a', 'b', 'C', 'B', 'A', 'c', 'a', 'D', 'A')), poly);
+        assert_eq!(b"dabCBAcaDA".to_vec(), poly);
+    }
+
+    #[test]
+    fn process_poly_edge_test() {
+        for &(input, output) in
+            &[("", ""), ("a", "a"), ("aA", ""), ("abBA", ""), ("aabAAB", "aabAAB"), ("aA1", "1")]
+        {
+            let mut poly = parse_poly(input);
+            assert_eq!(output.len(), process_poly(&mut poly));
+            assert_eq!(output.as_bytes(), &poly[..]);
+        }
     }
 
     #[test]
     fn get_unique_units_test() {
         let poly = parse_poly("dabAcCaCBAcCcaDA");
         let u = get_unique_units(&poly);
-        let mut sorted: Vec<char> = u.iter().cloned().collect();
+        let mut sorted: Vec<u8> = u.iter().cloned().collect();
         sorted.sort();
 
-        assert_eq!(vec!('a', 'b', 'c', 'd'), sorted);
+        assert_eq!(vec!(b'a', b'b', b'c', b'd'), sorted);
     }
 
     #[test]
     fn remove_unit_test() {
         let poly = parse_poly("dabAcCaCBAcCcaDA");
 
-        assert_eq!(parse_poly("dbcCCBcCcD"), remove_unit(&poly, 'a'));
-        assert_eq!(parse_poly("daAcCaCAcCcaDA"), remove_unit(&poly, 'b'));
-        assert_eq!(parse_poly("dabAaBAaDA"), remove_unit(&poly, 'c'));
-        assert_eq!(parse_poly("abAcCaCBAcCcaA"), remove_unit(&poly, 'd'));
+        assert_eq!(parse_poly("dbcCCBcCcD"), remove_unit(&poly, b'a'));
+        assert_eq!(parse_poly("daAcCaCAcCcaDA"), remove_unit(&poly, b'b'));
+        assert_eq!(parse_poly("dabAaBAaDA"), remove_unit(&poly, b'c'));
+        assert_eq!(parse_poly("abAcCaCBAcCcaA"), remove_unit(&poly, b'd'));
     }
 
     #[test]
     fn find_best_removal_test() {
         let poly = parse_poly("dabAcCaCBAcCcaDA");
-        assert_eq!(('c', 4), find_best_removal(&poly));
+        assert_eq!(Some(('c', 4)), find_best_removal(&poly));
+    }
+
+    #[test]
+    fn removal_lengths_test() {
+        let poly = parse_poly("dabAcCaCBAcCcaDA");
+        let lengths: Vec<(char, usize)> = removal_lengths(&poly, &OppositeCase)
+            .into_iter()
+            .map(|(u, l)| (u as char, l))
+            .collect();
+        assert_eq!(vec!(('a', 6), ('b', 8), ('c', 4), ('d', 6)), lengths);
+
+        // Both removals leave nothing behind, so the earlier unit wins.
+        assert_eq!(Some(('a', 0)), find_best_removal(&parse_poly("abAB")));
+        assert!(removal_lengths(&parse_poly(""), &OppositeCase).is_empty());
+        assert_eq!(None, find_best_removal(&parse_poly("")));
+
+        // Units that react away completely are still there to remove.
+        let lengths = removal_lengths(&parse_poly("aA"), &OppositeCase);
+        assert_eq!(vec![(b'a', 0)], lengths.into_iter().collect::<Vec<_>>());
+        assert_eq!(Some(('a', 0)), find_best_removal(&parse_poly("aA")));
+        let lengths = removal_lengths(&parse_poly("xXab"), &OppositeCase);
+        assert_eq!(vec![(b'a', 1), (b'b', 1), (b'x', 2)], lengths.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn removal_lengths_input_test() {
+        // Working from the reduced polymer must match working from the raw one.
+        let poly = read(File::open("input.txt").unwrap()).unwrap();
+        let lengths = removal_lengths(&poly, &OppositeCase);
+        for (&u, &len) in &lengths {
+            assert_eq!(process_poly(&mut remove_unit(&poly, u)), len);
+        }
+        assert_eq!(26, lengths.len());
+    }
+
+    #[test]
+    fn digits_rule_test() {
+        let rule = DigitsSumTo(10);
+        assert_eq!(
+            (Some(b'9'), Some(b'5'), None, None),
+            (rule.partner(b'1'), rule.partner(b'5'), rule.partner(b'0'), rule.partner(b'a'))
+        );
+        assert_eq!((b'1', b'1'), (rule.unit(b'1'), rule.unit(b'9')));
+
+        let mut poly = parse_poly("3197355a0");
+        assert_eq!(3, process_poly_with(&mut poly, &rule));
+        assert_eq!(b"3a0".to_vec(), poly);
+
+        assert_eq!(parse_poly("373550"), remove_unit_with(&parse_poly("319735590"), b'9', &rule));
+        assert_eq!(Some(('a', 0)), find_best_removal_with(&parse_poly("3a7"), &rule));
+    }
+
+    #[test]
+    fn pair_table_rule_test() {
+        let rule = PairTable::from_alphabets("([{", ")]}").unwrap();
+        let mut poly = parse_poly("a([]{})(]");
+        assert_eq!(3, process_poly_with(&mut poly, &rule));
+        assert_eq!(b"a(]".to_vec(), poly);
+        assert_eq!(Some(('(', 2)), find_best_removal_with(&parse_poly("a([]{})(]"), &rule));
+
+        // A unit can only have one partner.
+        assert!(PairTable::new(&[(b'a', b'b'), (b'a', b'c')]).is_err());
+        assert!(PairTable::new(&[(b'a', b'b'), (b'b', b'a'), (b'x', b'x')]).is_ok());
+        assert!(PairTable::from_alphabets("ab", "A").is_err());
+
+        // The table can spell out the puzzle's rule.
+        let letters = PairTable::from_alphabets("abcd", "ABCD").unwrap();
+        let mut poly = parse_poly("dabAcCaCBAcCcaDA");
+        assert_eq!(10, process_poly_with(&mut poly, &letters));
+        assert_eq!(
+            Some(('C', 4)),
+            find_best_removal_with(&parse_poly("dabAcCaCBAcCcaDA"), &letters)
+        );
+    }
+
+    #[test]
+    fn parse_rule_test() {
+        let poly = parse_poly("dabAcCaCBAcCcaDA");
+        let rule = parse_rule("letters").unwrap();
+        assert_eq!(Some(('c', 4)), find_best_removal_with(&poly, rule.as_ref()));
+
+        let rule = parse_rule("digits:10").unwrap();
+        assert_eq!(Some(b'9'), rule.partner(b'1'));
+        let rule = parse_rule("pairs:([:)]").unwrap();
+        assert_eq!((Some(b')'), Some(b'[')), (rule.partner(b'('), rule.partner(b']')));
+
+        for bad in &["", "digits:", "digits:300", "pairs:ab", "pairs:ab:A", "words"] {
+            assert!(parse_rule(bad).is_err(), "{}", bad);
+        }
     }
 }